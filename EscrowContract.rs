@@ -1,23 +1,252 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as instructions_sysvar, load_current_index_checked, load_instruction_at_checked,
+};
 use anchor_lang::system_program;
+use anchor_spl::token::{self, CloseAccount, Mint, Token, TokenAccount, Transfer};
 
 declare_id!("BjCuWasrQsLPcT9EpYHMBkFNR2sgtPNTvJpR7DD2PbV8");
 
+pub const MAX_MILESTONES: usize = 10;
+pub const MAX_PAYEES: usize = 5;
+pub const MAX_DEPOSITS: usize = 10;
+pub const MAX_FEE_BPS: u16 = 1_000;
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const MAX_EVIDENCE_URI_LEN: usize = 200;
+pub const EVIDENCE_WINDOW: i64 = 3 * 24 * 60 * 60;
+
+// Outcome byte appended to oracle attestations: `escrow || created_at || condition || outcome`
+pub const ORACLE_OUTCOME_REFUND: u8 = 0;
+pub const ORACLE_OUTCOME_RELEASE: u8 = 1;
+
 #[program]
 pub mod escrow_contract {
     use super::*;
 
+    // -------------------------------
+    // Initialize global escrow config
+    // -------------------------------
+    // Only the program's upgrade authority may create it, becoming `admin`
+    pub fn init_config(ctx: Context<InitConfig>, fee_bps: u16, fee_recipient: Pubkey) -> Result<()> {
+        require!(fee_bps <= MAX_FEE_BPS, EscrowError::FeeTooHigh);
+
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.fee_bps = fee_bps;
+        config.fee_recipient = fee_recipient;
+        config.bump = ctx.bumps.config;
+
+        Ok(())
+    }
+
+    // -------------------------------
+    // Update fee settings (admin only)
+    // -------------------------------
+    pub fn update_config(ctx: Context<UpdateConfig>, fee_bps: u16, fee_recipient: Pubkey) -> Result<()> {
+        require!(fee_bps <= MAX_FEE_BPS, EscrowError::FeeTooHigh);
+
+        let config = &mut ctx.accounts.config;
+        config.fee_bps = fee_bps;
+        config.fee_recipient = fee_recipient;
+
+        Ok(())
+    }
+
     // -------------------------------
     // Initialize Escrow
     // -------------------------------
-    pub fn init_escrow(ctx: Context<InitEscrow>) -> Result<()> {
+    // `release_after` / `refund_after` are unix timestamps, 0 disables them.
+    // An empty `milestones` list creates a plain single-payout escrow.
+    pub fn init_escrow(
+        ctx: Context<InitEscrow>,
+        release_after: i64,
+        refund_after: i64,
+        milestones: Vec<MilestoneInput>,
+    ) -> Result<()> {
+        require!(release_after >= 0 && refund_after >= 0, EscrowError::InvalidDeadline);
+        require!(milestones.len() <= MAX_MILESTONES, EscrowError::TooManyMilestones);
+        require!(
+            milestones.iter().all(|m| m.amount > 0),
+            EscrowError::InvalidAmount
+        );
+
+        let escrow = &mut ctx.accounts.escrow_pda;
+        escrow.buyer = ctx.accounts.buyer.key();
+        escrow.seller = ctx.accounts.seller.key();
+        escrow.amount = 0;
+        escrow.state = EscrowState::Pending;
+        escrow.release_after = release_after;
+        escrow.refund_after = refund_after;
+        escrow.milestones = milestones
+            .into_iter()
+            .map(|m| Milestone {
+                amount: m.amount,
+                description_hash: m.description_hash,
+                status: MilestoneStatus::Pending,
+            })
+            .collect();
+        // Reject milestone lists whose total would overflow
+        escrow.milestone_total()?;
+        escrow.hashlock = [0; 32];
+        escrow.timelock = 0;
+        escrow.agreed_amount = 0;
+        escrow.seller_payout = 0;
+        escrow.terms_hash = [0; 32];
+        escrow.oracle = Pubkey::default();
+        escrow.condition = [0; 32];
+        escrow.payees = Vec::new();
+        escrow.disputed_at = 0;
+        escrow.buyer_evidence_count = 0;
+        escrow.seller_evidence_count = 0;
+        escrow.deposits = Vec::new();
+        escrow.net_deposited = 0;
+        escrow.bump = ctx.bumps.escrow_pda;
+
+        let now = Clock::get()?.unix_timestamp;
+        escrow.created_at = now;
+        escrow.updated_at = now;
+
+        emit!(EscrowCreated {
+            escrow: escrow.key(),
+            buyer: escrow.buyer,
+            seller: escrow.seller,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    // -------------------------------
+    // Initialize hash time-locked escrow
+    // -------------------------------
+    // Seller claims by revealing `sha256(preimage) == hashlock` before `timelock`,
+    // afterwards only the buyer can refund.
+    pub fn init_htlc(ctx: Context<InitEscrow>, hashlock: [u8; 32], timelock: i64) -> Result<()> {
+        require!(hashlock != [0; 32], EscrowError::InvalidHashlock);
+        require!(
+            timelock > Clock::get()?.unix_timestamp,
+            EscrowError::InvalidDeadline
+        );
+
         let escrow = &mut ctx.accounts.escrow_pda;
         escrow.buyer = ctx.accounts.buyer.key();
         escrow.seller = ctx.accounts.seller.key();
         escrow.amount = 0;
         escrow.state = EscrowState::Pending;
+        escrow.release_after = 0;
+        escrow.refund_after = 0;
+        escrow.milestones = Vec::new();
+        escrow.hashlock = hashlock;
+        escrow.timelock = timelock;
+        escrow.agreed_amount = 0;
+        escrow.seller_payout = 0;
+        escrow.terms_hash = [0; 32];
+        escrow.oracle = Pubkey::default();
+        escrow.condition = [0; 32];
+        escrow.payees = Vec::new();
+        escrow.disputed_at = 0;
+        escrow.buyer_evidence_count = 0;
+        escrow.seller_evidence_count = 0;
+        escrow.deposits = Vec::new();
+        escrow.net_deposited = 0;
         escrow.bump = ctx.bumps.escrow_pda;
 
+        let now = Clock::get()?.unix_timestamp;
+        escrow.created_at = now;
+        escrow.updated_at = now;
+
+        emit!(EscrowCreated {
+            escrow: escrow.key(),
+            buyer: escrow.buyer,
+            seller: escrow.seller,
+            timestamp: now,
+        });
+        Ok(())
+    }
+
+    // -------------------------------
+    // Require an oracle attestation to settle
+    // -------------------------------
+    // Must be set before the seller accepts so the condition is part of the agreed terms
+    pub fn set_oracle_condition(
+        ctx: Context<SetOracleCondition>,
+        oracle: Pubkey,
+        condition: [u8; 32],
+    ) -> Result<()> {
+        require!(oracle != Pubkey::default(), EscrowError::InvalidOracle);
+
+        let escrow = &mut ctx.accounts.escrow_pda;
+        require!(escrow.state == EscrowState::Pending, EscrowError::InvalidState);
+        require!(escrow.milestones.is_empty(), EscrowError::HasMilestones);
+
+        escrow.oracle = oracle;
+        escrow.condition = condition;
+        escrow.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    // -------------------------------
+    // Seller accepts the escrow terms
+    // -------------------------------
+    // An empty `payees` list pays the full release to the seller
+    pub fn accept_escrow(
+        ctx: Context<AcceptEscrow>,
+        amount: u64,
+        terms_hash: [u8; 32],
+        payees: Vec<Payee>,
+    ) -> Result<()> {
+        require!(amount > 0, EscrowError::InvalidAmount);
+        validate_payees(&payees)?;
+
+        let escrow = &mut ctx.accounts.escrow_pda;
+        require!(escrow.state == EscrowState::Pending, EscrowError::InvalidState);
+        if !escrow.milestones.is_empty() {
+            require!(
+                amount == escrow.milestone_total()?,
+                EscrowError::MilestoneTotalMismatch
+            );
+        }
+
+        escrow.agreed_amount = amount;
+        escrow.seller_payout = amount;
+        escrow.terms_hash = terms_hash;
+        escrow.payees = payees;
+        escrow.state = EscrowState::Accepted;
+        escrow.updated_at = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    // -------------------------------
+    // Seller declines, closing the escrow
+    // -------------------------------
+    pub fn decline_escrow(ctx: Context<DeclineEscrow>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_pda;
+        require!(
+            escrow.state == EscrowState::Pending || escrow.state == EscrowState::Accepted,
+            EscrowError::InvalidState
+        );
+        require!(escrow.amount == 0, EscrowError::InvalidState);
+
+        // Rent is returned to the buyer by the `close` constraint
+        Ok(())
+    }
+
+    // -------------------------------
+    // Buyer cancels before funding, closing the escrow
+    // -------------------------------
+    // Lets the buyer walk away from a seller who never answers
+    pub fn cancel_escrow(ctx: Context<CloseEscrow>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_pda;
+        require!(
+            escrow.state == EscrowState::Pending || escrow.state == EscrowState::Accepted,
+            EscrowError::InvalidState
+        );
+        require!(escrow.amount == 0, EscrowError::InvalidState);
+
+        // Rent is returned to the buyer by the `close` constraint
         Ok(())
     }
 
@@ -28,6 +257,10 @@ pub mod escrow_contract {
         require!(amount > 0, EscrowError::InvalidAmount);
 
         let escrow = &mut ctx.accounts.escrow_pda;
+        require!(
+            escrow.state == EscrowState::Accepted || escrow.state == EscrowState::BuyerDeposit,
+            EscrowError::NotAccepted
+        );
 
         // Transfer SOL from buyer to PDA
         let cpi = CpiContext::new(
@@ -43,130 +276,2257 @@ pub mod escrow_contract {
             .amount
             .checked_add(amount)
             .ok_or(EscrowError::AmountOverflow)?;
+        // Capped on everything deposited so far, not the remaining balance,
+        // so paid-out milestones cannot be topped up again
+        escrow.net_deposited = escrow
+            .net_deposited
+            .checked_add(amount)
+            .ok_or(EscrowError::AmountOverflow)?;
+        require!(
+            escrow.net_deposited <= escrow.agreed_amount,
+            EscrowError::ExceedsAgreedAmount
+        );
+        require!(escrow.deposits.len() < MAX_DEPOSITS, EscrowError::TooManyDeposits);
+
+        let now = Clock::get()?.unix_timestamp;
+        escrow.deposits.push(DepositEntry {
+            amount,
+            timestamp: now,
+        });
         escrow.state = EscrowState::BuyerDeposit;
+        escrow.updated_at = now;
+        assert_reconciled(escrow)?;
 
+        emit!(Deposited {
+            escrow: escrow.key(),
+            buyer: escrow.buyer,
+            amount,
+            total: escrow.amount,
+            timestamp: now,
+        });
         Ok(())
     }
 
 // -------------------------------
 // Release SOL to seller
 // -------------------------------
-pub fn release(ctx: Context<Release>) -> Result<()> {
+pub fn release<'info>(
+    ctx: Context<'_, '_, '_, 'info, Release<'info>>,
+    close_account: bool,
+) -> Result<()> {
     let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
     require!(escrow.amount > 0, EscrowError::InvalidAmount);
     require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+    require!(escrow.milestones.is_empty(), EscrowError::HasMilestones);
+    if escrow.has_oracle() {
+        verify_oracle_attestation(&ctx.accounts.instructions, escrow, ORACLE_OUTCOME_RELEASE)?;
+    }
+
+    // An amended payout may leave part of the deposit owed back to the buyer
+    let seller_amount = escrow.seller_share();
+    let buyer_amount = escrow.amount - seller_amount;
+
+    // Manual lamport transfer (works with data accounts)
+    let fee = pay_seller(
+        &escrow.to_account_info(),
+        &ctx.accounts.seller.to_account_info(),
+        &ctx.accounts.fee_recipient.to_account_info(),
+        &ctx.accounts.config,
+        &escrow.payees,
+        ctx.remaining_accounts,
+        seller_amount,
+    )?;
+    **escrow.to_account_info().try_borrow_mut_lamports()? -= buyer_amount;
+    **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += buyer_amount;
+
+    escrow.amount = 0;
+    escrow.state = EscrowState::Completed;
+    emit_settlement(escrow, seller_amount, fee, buyer_amount)?;
+
+    if close_account {
+        escrow.close(ctx.accounts.buyer.to_account_info())?;
+    }
+    Ok(())
+}
+
+// -------------------------------
+// Refund SOL to buyer
+// -------------------------------
+pub fn refund(ctx: Context<Refund>, close_account: bool) -> Result<()> {
+    let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
+    require!(escrow.amount > 0, EscrowError::InvalidAmount);
+    check_refundable(escrow, &ctx.accounts.instructions)?;
 
     let amount = escrow.amount;
 
     // Manual lamport transfer (works with data accounts)
     **escrow.to_account_info().try_borrow_mut_lamports()? -= amount;
-    **ctx.accounts.seller.to_account_info().try_borrow_mut_lamports()? += amount;
+    **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += amount;
+
+    escrow.amount = 0;
+    escrow.state = EscrowState::Cancelled;
+    emit_settlement(escrow, 0, 0, amount)?;
+
+    if close_account {
+        escrow.close(ctx.accounts.buyer.to_account_info())?;
+    }
+    Ok(())
+}
+
+// -------------------------------
+// Refund part of the deposit to buyer
+// -------------------------------
+pub fn partial_refund(ctx: Context<Refund>, amount: u64) -> Result<()> {
+    require!(amount > 0, EscrowError::InvalidAmount);
+
+    let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
+    require!(amount <= escrow.amount, EscrowError::InsufficientFunds);
+    check_refundable(escrow, &ctx.accounts.instructions)?;
+
+    **escrow.to_account_info().try_borrow_mut_lamports()? -= amount;
+    **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += amount;
+
+    escrow.amount -= amount;
+    escrow.net_deposited -= amount;
+    if escrow.amount == 0 {
+        escrow.state = EscrowState::Cancelled;
+    }
+    emit_settlement(escrow, 0, 0, amount)?;
+    Ok(())
+}
+
+// -------------------------------
+// Close settled escrow, returning rent to buyer
+// -------------------------------
+pub fn close_escrow(ctx: Context<CloseEscrow>) -> Result<()> {
+    let escrow = &ctx.accounts.escrow_pda;
+    require!(
+        escrow.state == EscrowState::Completed || escrow.state == EscrowState::Cancelled,
+        EscrowError::InvalidState
+    );
+    require!(escrow.amount == 0, EscrowError::InvalidState);
 
+    // Rent is returned to the buyer by the `close` constraint
+    Ok(())
+}
+
+// -------------------------------
+// Seller claims after release deadline
+// -------------------------------
+pub fn claim_after_deadline<'info>(
+    ctx: Context<'_, '_, '_, 'info, ClaimAfterDeadline<'info>>,
+) -> Result<()> {
+    let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
+    require!(escrow.amount > 0, EscrowError::InvalidAmount);
+    require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+    require!(escrow.release_after > 0, EscrowError::DeadlineNotSet);
+    // Oracle escrows only release against an attestation
+    require!(!escrow.has_oracle(), EscrowError::OracleRequired);
+
+    let now = Clock::get()?.unix_timestamp;
+    require!(now >= escrow.release_after, EscrowError::DeadlineNotReached);
+
+    let seller_amount = escrow.seller_share();
+    let buyer_amount = escrow.amount - seller_amount;
+
+    let fee = pay_seller(
+        &escrow.to_account_info(),
+        &ctx.accounts.seller.to_account_info(),
+        &ctx.accounts.fee_recipient.to_account_info(),
+        &ctx.accounts.config,
+        &escrow.payees,
+        ctx.remaining_accounts,
+        seller_amount,
+    )?;
+    **escrow.to_account_info().try_borrow_mut_lamports()? -= buyer_amount;
+    **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += buyer_amount;
+
+    escrow.settle_pending_milestones(MilestoneStatus::Released);
     escrow.amount = 0;
     escrow.state = EscrowState::Completed;
+    emit_settlement(escrow, seller_amount, fee, buyer_amount)?;
     Ok(())
 }
 
 // -------------------------------
-// Refund SOL to buyer
+// Buyer reclaims after refund deadline
 // -------------------------------
-pub fn refund(ctx: Context<Refund>) -> Result<()> {
+pub fn reclaim_after_deadline(ctx: Context<ReclaimAfterDeadline>) -> Result<()> {
     let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
     require!(escrow.amount > 0, EscrowError::InvalidAmount);
     require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+    require!(escrow.refund_after > 0, EscrowError::DeadlineNotSet);
+    // Oracle escrows only refund against an attestation; disputes cover a silent oracle
+    require!(!escrow.has_oracle(), EscrowError::OracleRequired);
+
+    let now = Clock::get()?.unix_timestamp;
+    require!(now >= escrow.refund_after, EscrowError::DeadlineNotReached);
 
     let amount = escrow.amount;
 
-    // Manual lamport transfer (works with data accounts)
     **escrow.to_account_info().try_borrow_mut_lamports()? -= amount;
     **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += amount;
 
+    escrow.settle_pending_milestones(MilestoneStatus::Refunded);
     escrow.amount = 0;
     escrow.state = EscrowState::Cancelled;
+    emit_settlement(escrow, 0, 0, amount)?;
+    Ok(())
+}
+
+// -------------------------------
+// Release a single milestone to seller
+// -------------------------------
+pub fn release_milestone<'info>(
+    ctx: Context<'_, '_, '_, 'info, Release<'info>>,
+    index: u8,
+) -> Result<()> {
+    let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
+    require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+
+    let amount = escrow.take_milestone(index, MilestoneStatus::Released)?;
+
+    let fee = pay_seller(
+        &escrow.to_account_info(),
+        &ctx.accounts.seller.to_account_info(),
+        &ctx.accounts.fee_recipient.to_account_info(),
+        &ctx.accounts.config,
+        &escrow.payees,
+        ctx.remaining_accounts,
+        amount,
+    )?;
+
+    let leftover = if escrow.all_milestones_settled() {
+        sweep_leftover(escrow, &ctx.accounts.buyer.to_account_info())?
+    } else {
+        0
+    };
+    emit_settlement(escrow, amount, fee, leftover)?;
     Ok(())
 }
+
+// -------------------------------
+// Refund a single milestone to buyer
+// -------------------------------
+pub fn refund_milestone(ctx: Context<Refund>, index: u8) -> Result<()> {
+    let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
+    require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+
+    let amount = escrow.take_milestone(index, MilestoneStatus::Refunded)?;
+
+    **escrow.to_account_info().try_borrow_mut_lamports()? -= amount;
+    **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += amount;
+    escrow.net_deposited -= amount;
+
+    let leftover = if escrow.all_milestones_settled() {
+        sweep_leftover(escrow, &ctx.accounts.buyer.to_account_info())?
+    } else {
+        0
+    };
+    emit_settlement(escrow, 0, 0, amount + leftover)?;
+    Ok(())
 }
 
 // -------------------------------
-// Escrow State
+// Propose new terms (buyer or seller)
 // -------------------------------
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
-pub enum EscrowState {
-    Pending,
-    BuyerDeposit,
-    Completed,
-    Cancelled,
+// `new_deadline` replaces `refund_after`; a `new_amount` of 0 is a mutual cancellation
+pub fn propose_amendment(
+    ctx: Context<ProposeAmendment>,
+    new_amount: u64,
+    new_deadline: i64,
+    seller_payout: u64,
+) -> Result<()> {
+    let escrow = &ctx.accounts.escrow_pda;
+    require!(
+        escrow.state == EscrowState::Accepted || escrow.state == EscrowState::BuyerDeposit,
+        EscrowError::InvalidState
+    );
+    require!(escrow.milestones.is_empty(), EscrowError::HasMilestones);
+    require!(!escrow.is_htlc(), EscrowError::InvalidState);
+    require!(new_deadline >= 0, EscrowError::InvalidDeadline);
+    require!(seller_payout <= new_amount, EscrowError::InvalidAmount);
+
+    let amendment = &mut ctx.accounts.amendment;
+    amendment.escrow = escrow.key();
+    amendment.escrow_created_at = escrow.created_at;
+    amendment.proposer = ctx.accounts.proposer.key();
+    amendment.new_amount = new_amount;
+    amendment.new_deadline = new_deadline;
+    amendment.seller_payout = seller_payout;
+    amendment.bump = ctx.bumps.amendment;
+
+    Ok(())
 }
 
 // -------------------------------
-// Escrow Account
+// Counterparty accepts pending amendment
 // -------------------------------
-#[account]
-pub struct EscrowAccount {
-    pub buyer: Pubkey,
-    pub seller: Pubkey,
-    pub amount: u64,
-    pub state: EscrowState,
-    pub bump: u8,
+pub fn accept_amendment(ctx: Context<AcceptAmendment>) -> Result<()> {
+    let amendment = &ctx.accounts.amendment;
+    let escrow = &mut ctx.accounts.escrow_pda;
+
+    // Only the proposer's counterparty in this deal may accept
+    let counterparty = if amendment.proposer == escrow.buyer {
+        escrow.seller
+    } else if amendment.proposer == escrow.seller {
+        escrow.buyer
+    } else {
+        return err!(EscrowError::Unauthorized);
+    };
+    require!(
+        ctx.accounts.approver.key() == counterparty,
+        EscrowError::Unauthorized
+    );
+
+    assert_reconciled(escrow)?;
+    require!(
+        escrow.state == EscrowState::Accepted || escrow.state == EscrowState::BuyerDeposit,
+        EscrowError::InvalidState
+    );
+
+    // Anything deposited above the new amount goes back to the buyer
+    let excess = escrow.amount.saturating_sub(amendment.new_amount);
+    **escrow.to_account_info().try_borrow_mut_lamports()? -= excess;
+    **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += excess;
+
+    escrow.amount -= excess;
+    escrow.net_deposited -= excess;
+    escrow.agreed_amount = amendment.new_amount;
+    escrow.refund_after = amendment.new_deadline;
+    escrow.seller_payout = amendment.seller_payout;
+    if amendment.new_amount == 0 {
+        escrow.state = EscrowState::Cancelled;
+    }
+    if excess > 0 {
+        emit_settlement(escrow, 0, 0, excess)?;
+    } else {
+        escrow.updated_at = Clock::get()?.unix_timestamp;
+    }
+
+    // Amendment rent is returned to the proposer by the `close` constraint
+    Ok(())
 }
 
 // -------------------------------
-// Accounts Context
+// Withdraw or reject pending amendment
 // -------------------------------
-#[derive(Accounts)]
-pub struct InitEscrow<'info> {
-    #[account(mut)]
-    pub buyer: Signer<'info>,
-    /// CHECK: Seller is just a pubkey
-    pub seller: SystemAccount<'info>,
+// Also clears amendments left over from an earlier deal on the same escrow PDA
+pub fn reject_amendment(_ctx: Context<RejectAmendment>) -> Result<()> {
+    // Amendment rent is returned to the proposer by the `close` constraint
+    Ok(())
+}
 
-    #[account(
-        init,
-        payer = buyer,
-        space = 8 + 32 + 32 + 8 + 1 + 1,
-        seeds = [b"escrow", buyer.key().as_ref()],
-        bump
-    )]
-    pub escrow_pda: Account<'info, EscrowAccount>,
+// -------------------------------
+// Either party raises a dispute
+// -------------------------------
+pub fn raise_dispute(ctx: Context<RaiseDispute>) -> Result<()> {
+    let escrow = &mut ctx.accounts.escrow_pda;
+    require!(escrow.amount > 0, EscrowError::InvalidAmount);
+    require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
 
-    pub system_program: Program<'info, System>,
+    let now = Clock::get()?.unix_timestamp;
+    escrow.state = EscrowState::Disputed;
+    escrow.disputed_at = now;
+    escrow.updated_at = now;
+
+    emit!(Disputed {
+        escrow: escrow.key(),
+        raised_by: ctx.accounts.party.key(),
+        amount: escrow.amount,
+        timestamp: now,
+    });
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct Deposit<'info> {
-    #[account(mut)]
-    pub buyer: Signer<'info>,
+// -------------------------------
+// Submit evidence while disputed
+// -------------------------------
+// `index` must be the submitting party's next evidence index; records are
+// also keyed by `disputed_at`, so a reused escrow PDA starts from fresh slots
+pub fn submit_evidence(
+    ctx: Context<SubmitEvidence>,
+    index: u8,
+    content_hash: [u8; 32],
+    uri: String,
+) -> Result<()> {
+    require!(uri.len() <= MAX_EVIDENCE_URI_LEN, EscrowError::UriTooLong);
 
-    #[account(mut, seeds = [b"escrow", buyer.key().as_ref()], bump = escrow_pda.bump)]
-    pub escrow_pda: Account<'info, EscrowAccount>,
+    let escrow = &mut ctx.accounts.escrow_pda;
+    require!(escrow.state == EscrowState::Disputed, EscrowError::InvalidState);
 
-    pub system_program: Program<'info, System>,
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        now <= escrow.disputed_at + EVIDENCE_WINDOW,
+        EscrowError::EvidenceWindowClosed
+    );
+
+    let party = ctx.accounts.party.key();
+    let count = if party == escrow.buyer {
+        &mut escrow.buyer_evidence_count
+    } else {
+        &mut escrow.seller_evidence_count
+    };
+    require!(index == *count, EscrowError::InvalidEvidenceIndex);
+    *count = count.checked_add(1).ok_or(EscrowError::AmountOverflow)?;
+    escrow.updated_at = now;
+
+    let evidence = &mut ctx.accounts.evidence;
+    evidence.escrow = escrow.key();
+    evidence.party = party;
+    evidence.content_hash = content_hash;
+    evidence.uri = uri;
+    evidence.submitted_at = now;
+    evidence.bump = ctx.bumps.evidence;
+
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct Release<'info> {
-    #[account(mut)]
-    pub buyer: Signer<'info>,
+// -------------------------------
+// Arbiter resolves dispute after the evidence window
+// -------------------------------
+pub fn resolve_dispute<'info>(
+    ctx: Context<'_, '_, '_, 'info, ResolveDispute<'info>>,
+    release_to_seller: bool,
+) -> Result<()> {
+    let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
+    require!(escrow.state == EscrowState::Disputed, EscrowError::InvalidState);
+    require!(
+        Clock::get()?.unix_timestamp > escrow.disputed_at + EVIDENCE_WINDOW,
+        EscrowError::EvidenceWindowOpen
+    );
 
-    /// CHECK: Seller receives SOL
-    #[account(mut)]
-    pub seller: SystemAccount<'info>,
+    let amount = escrow.amount;
+    let mut fee = 0;
 
-    #[account(mut, seeds = [b"escrow", buyer.key().as_ref()], bump = escrow_pda.bump)]
-    pub escrow_pda: Account<'info, EscrowAccount>,
+    if release_to_seller {
+        // Honour an amended payout; the remainder goes back to the buyer
+        let seller_amount = escrow.seller_share();
+        let buyer_amount = amount - seller_amount;
 
-    pub system_program: Program<'info, System>,
+        fee = pay_seller(
+            &escrow.to_account_info(),
+            &ctx.accounts.seller.to_account_info(),
+            &ctx.accounts.fee_recipient.to_account_info(),
+            &ctx.accounts.config,
+            &escrow.payees,
+            ctx.remaining_accounts,
+            seller_amount,
+        )?;
+        **escrow.to_account_info().try_borrow_mut_lamports()? -= buyer_amount;
+        **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += buyer_amount;
+        escrow.settle_pending_milestones(MilestoneStatus::Released);
+        escrow.state = EscrowState::Completed;
+    } else {
+        **escrow.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += amount;
+        escrow.settle_pending_milestones(MilestoneStatus::Refunded);
+        escrow.state = EscrowState::Cancelled;
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    escrow.amount = 0;
+    escrow.updated_at = now;
+
+    emit!(Resolved {
+        escrow: escrow.key(),
+        arbiter: ctx.accounts.arbiter.key(),
+        released_to_seller: release_to_seller,
+        amount,
+        fee,
+        timestamp: now,
+    });
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct Refund<'info> {
-    #[account(mut)]
-    pub buyer: Signer<'info>,
+// -------------------------------
+// Seller claims HTLC by revealing the preimage
+// -------------------------------
+pub fn claim_with_secret<'info>(
+    ctx: Context<'_, '_, '_, 'info, ClaimWithSecret<'info>>,
+    preimage: [u8; 32],
+) -> Result<()> {
+    let escrow = &mut ctx.accounts.escrow_pda;
+    assert_reconciled(escrow)?;
+    require!(escrow.is_htlc(), EscrowError::NotHtlc);
+    require!(!escrow.has_oracle(), EscrowError::OracleRequired);
+    require!(escrow.amount > 0, EscrowError::InvalidAmount);
+    require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+    require!(
+        Clock::get()?.unix_timestamp < escrow.timelock,
+        EscrowError::TimelockExpired
+    );
+    require!(
+        hash(&preimage).to_bytes() == escrow.hashlock,
+        EscrowError::InvalidPreimage
+    );
 
-    #[account(mut, seeds = [b"escrow", buyer.key().as_ref()], bump = escrow_pda.bump)]
-    pub escrow_pda: Account<'info, EscrowAccount>,
+    let amount = escrow.amount;
 
-    pub system_program: Program<'info, System>,
+    let fee = pay_seller(
+        &escrow.to_account_info(),
+        &ctx.accounts.seller.to_account_info(),
+        &ctx.accounts.fee_recipient.to_account_info(),
+        &ctx.accounts.config,
+        &escrow.payees,
+        ctx.remaining_accounts,
+        amount,
+    )?;
+
+    escrow.amount = 0;
+    escrow.state = EscrowState::Completed;
+    emit_settlement(escrow, amount, fee, 0)?;
+
+    emit!(SecretRevealed {
+        escrow: escrow.key(),
+        hashlock: escrow.hashlock,
+        preimage,
+    });
+    Ok(())
+}
+
+// -------------------------------
+// Initialize token swap (maker offers mint A for mint B)
+// -------------------------------
+// `nonce` lets a maker run several swaps; the swap account closes once settled
+pub fn init_swap(ctx: Context<InitSwap>, nonce: u64, amount_a: u64, amount_b: u64) -> Result<()> {
+    require!(amount_a > 0 && amount_b > 0, EscrowError::InvalidAmount);
+
+    let swap = &mut ctx.accounts.swap_pda;
+    swap.maker = ctx.accounts.maker.key();
+    swap.nonce = nonce;
+    swap.taker = ctx.accounts.taker.key();
+    swap.mint_a = ctx.accounts.mint_a.key();
+    swap.mint_b = ctx.accounts.mint_b.key();
+    swap.amount_a = amount_a;
+    swap.amount_b = amount_b;
+    swap.maker_deposited = false;
+    swap.taker_deposited = false;
+    swap.state = EscrowState::Pending;
+    swap.bump = ctx.bumps.swap_pda;
+
+    Ok(())
+}
+
+// -------------------------------
+// Deposit one side of the swap
+// -------------------------------
+pub fn deposit_swap(ctx: Context<DepositSwap>) -> Result<()> {
+    let swap = &mut ctx.accounts.swap_pda;
+    require!(swap.state == EscrowState::Pending, EscrowError::InvalidState);
+
+    let depositor = ctx.accounts.depositor.key();
+    let mint = ctx.accounts.depositor_token.mint;
+
+    let amount = if depositor == swap.maker {
+        require!(mint == swap.mint_a, EscrowError::MintMismatch);
+        require!(!swap.maker_deposited, EscrowError::AlreadyDeposited);
+        swap.maker_deposited = true;
+        swap.amount_a
+    } else if depositor == swap.taker {
+        require!(mint == swap.mint_b, EscrowError::MintMismatch);
+        require!(!swap.taker_deposited, EscrowError::AlreadyDeposited);
+        swap.taker_deposited = true;
+        swap.amount_b
+    } else {
+        return err!(EscrowError::Unauthorized);
+    };
+
+    let cpi = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.depositor_token.to_account_info(),
+            to: ctx.accounts.vault.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        },
+    );
+    token::transfer(cpi, amount)?;
+
+    if swap.maker_deposited && swap.taker_deposited {
+        swap.state = EscrowState::Funded;
+    }
+    Ok(())
+}
+
+// -------------------------------
+// Exchange both sides atomically
+// -------------------------------
+pub fn exchange(ctx: Context<Exchange>) -> Result<()> {
+    let swap = &mut ctx.accounts.swap_pda;
+    require!(swap.state == EscrowState::Funded, EscrowError::InvalidState);
+
+    let maker_key = swap.maker;
+    let nonce = swap.nonce.to_le_bytes();
+    let seeds = &[b"swap".as_ref(), maker_key.as_ref(), nonce.as_ref(), &[swap.bump]];
+    let signer = &[&seeds[..]];
+
+    // Vault A -> taker
+    token::transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.vault_a.to_account_info(),
+                to: ctx.accounts.taker_token_a.to_account_info(),
+                authority: swap.to_account_info(),
+            },
+            signer,
+        ),
+        swap.amount_a,
+    )?;
+
+    // Vault B -> maker
+    token::transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.vault_b.to_account_info(),
+                to: ctx.accounts.maker_token_b.to_account_info(),
+                authority: swap.to_account_info(),
+            },
+            signer,
+        ),
+        swap.amount_b,
+    )?;
+
+    close_swap_vaults(
+        &ctx.accounts.token_program,
+        swap,
+        &ctx.accounts.vault_a,
+        &ctx.accounts.vault_b,
+        &ctx.accounts.maker,
+        signer,
+    )?;
+
+    // Swap account rent is returned to the maker by the `close` constraint
+    swap.state = EscrowState::Completed;
+    Ok(())
+}
+
+// -------------------------------
+// Cancel swap before both sides are funded
+// -------------------------------
+pub fn cancel_swap(ctx: Context<CancelSwap>) -> Result<()> {
+    let swap = &mut ctx.accounts.swap_pda;
+    require!(swap.state == EscrowState::Pending, EscrowError::InvalidState);
+
+    let maker_key = swap.maker;
+    let nonce = swap.nonce.to_le_bytes();
+    let seeds = &[b"swap".as_ref(), maker_key.as_ref(), nonce.as_ref(), &[swap.bump]];
+    let signer = &[&seeds[..]];
+
+    if swap.maker_deposited {
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault_a.to_account_info(),
+                    to: ctx.accounts.maker_token_a.to_account_info(),
+                    authority: swap.to_account_info(),
+                },
+                signer,
+            ),
+            swap.amount_a,
+        )?;
+        swap.maker_deposited = false;
+    }
+
+    if swap.taker_deposited {
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault_b.to_account_info(),
+                    to: ctx.accounts.taker_token_b.to_account_info(),
+                    authority: swap.to_account_info(),
+                },
+                signer,
+            ),
+            swap.amount_b,
+        )?;
+        swap.taker_deposited = false;
+    }
+
+    close_swap_vaults(
+        &ctx.accounts.token_program,
+        swap,
+        &ctx.accounts.vault_a,
+        &ctx.accounts.vault_b,
+        &ctx.accounts.maker,
+        signer,
+    )?;
+
+    // Swap account rent is returned to the maker by the `close` constraint
+    swap.state = EscrowState::Cancelled;
+    Ok(())
+}
+
+// -------------------------------
+// Create linear vesting stream
+// -------------------------------
+// `cliff_ts` of 0 means no cliff. `nonce` lets a payer run several streams;
+// the stream account closes once fully withdrawn or cancelled.
+pub fn create_stream(
+    ctx: Context<CreateStream>,
+    nonce: u64,
+    amount: u64,
+    start_ts: i64,
+    cliff_ts: i64,
+    end_ts: i64,
+) -> Result<()> {
+    require!(amount > 0, EscrowError::InvalidAmount);
+    require!(start_ts >= 0 && start_ts < end_ts, EscrowError::InvalidSchedule);
+    let cliff_ts = if cliff_ts == 0 { start_ts } else { cliff_ts };
+    require!(
+        cliff_ts >= start_ts && cliff_ts <= end_ts,
+        EscrowError::InvalidSchedule
+    );
+
+    let stream = &mut ctx.accounts.stream_pda;
+
+    // Transfer SOL from payer to PDA
+    let cpi = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        system_program::Transfer {
+            from: ctx.accounts.payer.to_account_info(),
+            to: stream.to_account_info(),
+        },
+    );
+    system_program::transfer(cpi, amount)?;
+
+    stream.payer = ctx.accounts.payer.key();
+    stream.nonce = nonce;
+    stream.recipient = ctx.accounts.recipient.key();
+    stream.amount = amount;
+    stream.withdrawn = 0;
+    stream.start_ts = start_ts;
+    stream.cliff_ts = cliff_ts;
+    stream.end_ts = end_ts;
+    stream.state = EscrowState::BuyerDeposit;
+    stream.bump = ctx.bumps.stream_pda;
+
+    Ok(())
+}
+
+// -------------------------------
+// Recipient withdraws vested funds
+// -------------------------------
+pub fn withdraw_vested(ctx: Context<WithdrawVested>) -> Result<()> {
+    let stream = &mut ctx.accounts.stream_pda;
+    require!(stream.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+
+    let vested = stream.vested_amount(Clock::get()?.unix_timestamp)?;
+    let claimable = vested
+        .checked_sub(stream.withdrawn)
+        .ok_or(EscrowError::AmountOverflow)?;
+    require!(claimable > 0, EscrowError::NothingVested);
+
+    **stream.to_account_info().try_borrow_mut_lamports()? -= claimable;
+    **ctx.accounts.recipient.to_account_info().try_borrow_mut_lamports()? += claimable;
+
+    stream.withdrawn = vested;
+    if stream.withdrawn == stream.amount {
+        stream.state = EscrowState::Completed;
+        stream.close(ctx.accounts.payer.to_account_info())?;
+    }
+    Ok(())
+}
+
+// -------------------------------
+// Payer cancels stream, splitting vested / unvested
+// -------------------------------
+pub fn cancel_stream(ctx: Context<CancelStream>) -> Result<()> {
+    let stream = &mut ctx.accounts.stream_pda;
+    require!(stream.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+
+    let vested = stream.vested_amount(Clock::get()?.unix_timestamp)?;
+    let owed = vested
+        .checked_sub(stream.withdrawn)
+        .ok_or(EscrowError::AmountOverflow)?;
+    let unvested = stream
+        .amount
+        .checked_sub(vested)
+        .ok_or(EscrowError::AmountOverflow)?;
+
+    **stream.to_account_info().try_borrow_mut_lamports()? -= owed + unvested;
+    **ctx.accounts.recipient.to_account_info().try_borrow_mut_lamports()? += owed;
+    **ctx.accounts.payer.to_account_info().try_borrow_mut_lamports()? += unvested;
+
+    // Stream account rent is returned to the payer by the `close` constraint
+    stream.withdrawn = vested;
+    stream.state = EscrowState::Cancelled;
+    Ok(())
+}
+
+// -------------------------------
+// Seller lists an NFT for SOL, locking it in escrow
+// -------------------------------
+// `nonce` lets a seller run several listings; the listing closes once settled
+pub fn list_nft(ctx: Context<ListNft>, nonce: u64, price: u64) -> Result<()> {
+    require!(price > 0, EscrowError::InvalidAmount);
+
+    let cpi = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.seller_nft_account.to_account_info(),
+            to: ctx.accounts.nft_vault.to_account_info(),
+            authority: ctx.accounts.seller.to_account_info(),
+        },
+    );
+    token::transfer(cpi, 1)?;
+
+    let nft_escrow = &mut ctx.accounts.nft_escrow;
+    nft_escrow.seller = ctx.accounts.seller.key();
+    nft_escrow.nonce = nonce;
+    nft_escrow.buyer = ctx.accounts.buyer.key();
+    nft_escrow.nft_mint = ctx.accounts.nft_mint.key();
+    nft_escrow.price = price;
+    nft_escrow.state = EscrowState::Pending;
+    nft_escrow.bump = ctx.bumps.nft_escrow;
+
+    Ok(())
+}
+
+// -------------------------------
+// Buyer deposits the SOL price
+// -------------------------------
+pub fn deposit_payment(ctx: Context<DepositPayment>) -> Result<()> {
+    let nft_escrow = &mut ctx.accounts.nft_escrow;
+    require!(nft_escrow.state == EscrowState::Pending, EscrowError::InvalidState);
+
+    let cpi = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        system_program::Transfer {
+            from: ctx.accounts.buyer.to_account_info(),
+            to: nft_escrow.to_account_info(),
+        },
+    );
+    system_program::transfer(cpi, nft_escrow.price)?;
+
+    nft_escrow.state = EscrowState::Funded;
+    Ok(())
+}
+
+// -------------------------------
+// Settle: NFT -> buyer, SOL -> seller
+// -------------------------------
+pub fn settle(ctx: Context<Settle>) -> Result<()> {
+    let nft_escrow = &mut ctx.accounts.nft_escrow;
+    require!(nft_escrow.state == EscrowState::Funded, EscrowError::InvalidState);
+
+    let seller_key = nft_escrow.seller;
+    let nonce = nft_escrow.nonce.to_le_bytes();
+    let seeds = &[
+        b"nft_escrow".as_ref(),
+        seller_key.as_ref(),
+        nonce.as_ref(),
+        &[nft_escrow.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    token::transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.nft_vault.to_account_info(),
+                to: ctx.accounts.buyer_nft_account.to_account_info(),
+                authority: nft_escrow.to_account_info(),
+            },
+            signer,
+        ),
+        1,
+    )?;
+
+    let price = nft_escrow.price;
+    **nft_escrow.to_account_info().try_borrow_mut_lamports()? -= price;
+    **ctx.accounts.seller.to_account_info().try_borrow_mut_lamports()? += price;
+
+    close_nft_vault(
+        &ctx.accounts.token_program,
+        nft_escrow,
+        &ctx.accounts.nft_vault,
+        &ctx.accounts.seller,
+        signer,
+    )?;
+
+    // Listing rent is returned to the seller by the `close` constraint
+    nft_escrow.state = EscrowState::Completed;
+    Ok(())
+}
+
+// -------------------------------
+// Refund: NFT -> seller, SOL (if paid) -> buyer
+// -------------------------------
+// Once paid, only the seller may unwind; the buyer is entitled to settlement
+pub fn refund_nft(ctx: Context<RefundNft>) -> Result<()> {
+    let nft_escrow = &mut ctx.accounts.nft_escrow;
+    match nft_escrow.state {
+        EscrowState::Pending => {}
+        EscrowState::Funded => require!(
+            ctx.accounts.caller.key() == nft_escrow.seller,
+            EscrowError::Unauthorized
+        ),
+        _ => return err!(EscrowError::InvalidState),
+    }
+
+    let seller_key = nft_escrow.seller;
+    let nonce = nft_escrow.nonce.to_le_bytes();
+    let seeds = &[
+        b"nft_escrow".as_ref(),
+        seller_key.as_ref(),
+        nonce.as_ref(),
+        &[nft_escrow.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    token::transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.nft_vault.to_account_info(),
+                to: ctx.accounts.seller_nft_account.to_account_info(),
+                authority: nft_escrow.to_account_info(),
+            },
+            signer,
+        ),
+        1,
+    )?;
+
+    if nft_escrow.state == EscrowState::Funded {
+        let price = nft_escrow.price;
+        **nft_escrow.to_account_info().try_borrow_mut_lamports()? -= price;
+        **ctx.accounts.buyer.to_account_info().try_borrow_mut_lamports()? += price;
+    }
+
+    close_nft_vault(
+        &ctx.accounts.token_program,
+        nft_escrow,
+        &ctx.accounts.nft_vault,
+        &ctx.accounts.seller,
+        signer,
+    )?;
+
+    // Listing rent is returned to the seller by the `close` constraint
+    nft_escrow.state = EscrowState::Cancelled;
+    Ok(())
+}
+}
+
+// -------------------------------
+// Internal helpers
+// -------------------------------
+// Moves `amount` out of the escrow, sending the platform fee to the fee
+// recipient and the remainder to the seller, or split across the payees
+// (passed in order as remaining accounts) when the escrow has any.
+// Returns the fee taken.
+fn pay_seller<'info>(
+    escrow: &AccountInfo<'info>,
+    seller: &AccountInfo<'info>,
+    fee_recipient: &AccountInfo<'info>,
+    config: &EscrowConfig,
+    payees: &[Payee],
+    remaining_accounts: &[AccountInfo<'info>],
+    amount: u64,
+) -> Result<u64> {
+    let fee = (amount as u128)
+        .checked_mul(config.fee_bps as u128)
+        .ok_or(EscrowError::AmountOverflow)?
+        .checked_div(BPS_DENOMINATOR as u128)
+        .ok_or(EscrowError::AmountOverflow)? as u64;
+    let payout = amount
+        .checked_sub(fee)
+        .ok_or(EscrowError::AmountOverflow)?;
+
+    **escrow.try_borrow_mut_lamports()? -= amount;
+    **fee_recipient.try_borrow_mut_lamports()? += fee;
+
+    if payees.is_empty() {
+        **seller.try_borrow_mut_lamports()? += payout;
+        return Ok(fee);
+    }
+
+    require!(
+        remaining_accounts.len() == payees.len(),
+        EscrowError::PayeeMismatch
+    );
+
+    let mut distributed: u64 = 0;
+    for (i, (payee, account)) in payees.iter().zip(remaining_accounts).enumerate() {
+        require!(
+            account.key() == payee.recipient && account.is_writable,
+            EscrowError::PayeeMismatch
+        );
+
+        // The last payee absorbs rounding dust
+        let share = if i == payees.len() - 1 {
+            payout - distributed
+        } else {
+            ((payout as u128) * (payee.share_bps as u128) / BPS_DENOMINATOR as u128) as u64
+        };
+        distributed += share;
+
+        **account.try_borrow_mut_lamports()? += share;
+    }
+    Ok(fee)
+}
+
+fn validate_payees(payees: &[Payee]) -> Result<()> {
+    if payees.is_empty() {
+        return Ok(());
+    }
+    require!(payees.len() <= MAX_PAYEES, EscrowError::TooManyPayees);
+    require!(
+        payees.iter().all(|p| p.share_bps > 0),
+        EscrowError::InvalidPayeeShares
+    );
+
+    let total: u64 = payees.iter().map(|p| p.share_bps as u64).sum();
+    require!(total == BPS_DENOMINATOR, EscrowError::InvalidPayeeShares);
+    Ok(())
+}
+
+// Stamps `updated_at` and emits `Released` / `Refunded` for whichever side was paid
+fn emit_settlement(
+    escrow: &mut Account<EscrowAccount>,
+    released: u64,
+    fee: u64,
+    refunded: u64,
+) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    escrow.updated_at = now;
+
+    if released > 0 {
+        emit!(Released {
+            escrow: escrow.key(),
+            buyer: escrow.buyer,
+            seller: escrow.seller,
+            amount: released,
+            fee,
+            timestamp: now,
+        });
+    }
+    if refunded > 0 {
+        emit!(Refunded {
+            escrow: escrow.key(),
+            buyer: escrow.buyer,
+            amount: refunded,
+            timestamp: now,
+        });
+    }
+    Ok(())
+}
+
+// The PDA must always hold at least the escrowed SOL plus rent exemption.
+// Anyone can transfer lamports in, so a surplus is tolerated; it is never
+// paid out as escrow funds and goes to the buyer when the account closes
+fn assert_reconciled(escrow: &Account<EscrowAccount>) -> Result<()> {
+    let info = escrow.to_account_info();
+    let rent_exempt = Rent::get()?.minimum_balance(info.data_len());
+    let expected = rent_exempt
+        .checked_add(escrow.amount)
+        .ok_or(EscrowError::AmountOverflow)?;
+    require!(info.lamports() >= expected, EscrowError::BalanceMismatch);
+    Ok(())
+}
+
+// Completes a milestone escrow once its last milestone settles, returning
+// anything still held beyond the milestones to the buyer. Returns the sweep
+fn sweep_leftover<'info>(
+    escrow: &mut Account<'info, EscrowAccount>,
+    buyer: &AccountInfo<'info>,
+) -> Result<u64> {
+    let leftover = escrow.amount;
+    **escrow.to_account_info().try_borrow_mut_lamports()? -= leftover;
+    **buyer.try_borrow_mut_lamports()? += leftover;
+
+    escrow.amount = 0;
+    escrow.state = EscrowState::Completed;
+    Ok(leftover)
+}
+
+// Shared preconditions for returning deposited SOL to the buyer
+fn check_refundable(escrow: &Account<EscrowAccount>, ix_sysvar: &AccountInfo) -> Result<()> {
+    require!(escrow.state == EscrowState::BuyerDeposit, EscrowError::InvalidState);
+    require!(escrow.milestones.is_empty(), EscrowError::HasMilestones);
+    if escrow.is_htlc() {
+        require!(
+            Clock::get()?.unix_timestamp >= escrow.timelock,
+            EscrowError::TimelockActive
+        );
+    }
+    if escrow.has_oracle() {
+        verify_oracle_attestation(ix_sysvar, escrow, ORACLE_OUTCOME_REFUND)?;
+    }
+    Ok(())
+}
+
+// Checks that the instruction preceding this one is an Ed25519 program
+// verification of `escrow || created_at || condition || outcome` signed by the
+// escrow's oracle. `created_at` stops attestations replaying on a reused PDA
+fn verify_oracle_attestation(
+    ix_sysvar: &AccountInfo,
+    escrow: &Account<EscrowAccount>,
+    outcome: u8,
+) -> Result<()> {
+    let current = load_current_index_checked(ix_sysvar)? as usize;
+    require!(current > 0, EscrowError::MissingOracleAttestation);
+
+    let ix = load_instruction_at_checked(current - 1, ix_sysvar)?;
+    require!(
+        ix.program_id == ed25519_program::ID,
+        EscrowError::MissingOracleAttestation
+    );
+
+    // Ed25519 instruction data: [num_signatures, padding, offsets (7 x u16)...]
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, EscrowError::InvalidOracleAttestation);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+
+    // Signature, pubkey and message must all live in the Ed25519 instruction itself
+    require!(
+        read_u16(4) == u16::MAX && read_u16(8) == u16::MAX && read_u16(14) == u16::MAX,
+        EscrowError::InvalidOracleAttestation
+    );
+
+    let pubkey_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+
+    let pubkey = data
+        .get(pubkey_offset..pubkey_offset + 32)
+        .ok_or(EscrowError::InvalidOracleAttestation)?;
+    let message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(EscrowError::InvalidOracleAttestation)?;
+
+    let expected = [
+        escrow.key().as_ref(),
+        &escrow.created_at.to_le_bytes(),
+        escrow.condition.as_ref(),
+        &[outcome],
+    ]
+    .concat();
+    require!(
+        pubkey == escrow.oracle.as_ref() && message == expected.as_slice(),
+        EscrowError::InvalidOracleAttestation
+    );
+    Ok(())
+}
+
+// Returns the rent of the (empty) NFT vault to the seller who paid for it
+fn close_nft_vault<'info>(
+    token_program: &Program<'info, Token>,
+    nft_escrow: &Account<'info, NftEscrow>,
+    nft_vault: &Account<'info, TokenAccount>,
+    seller: &SystemAccount<'info>,
+    signer: &[&[&[u8]]],
+) -> Result<()> {
+    token::close_account(CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        CloseAccount {
+            account: nft_vault.to_account_info(),
+            destination: seller.to_account_info(),
+            authority: nft_escrow.to_account_info(),
+        },
+        signer,
+    ))
+}
+
+// Returns the rent of both (empty) swap vaults to the maker who paid for them
+fn close_swap_vaults<'info>(
+    token_program: &Program<'info, Token>,
+    swap: &Account<'info, SwapEscrow>,
+    vault_a: &Account<'info, TokenAccount>,
+    vault_b: &Account<'info, TokenAccount>,
+    maker: &SystemAccount<'info>,
+    signer: &[&[&[u8]]],
+) -> Result<()> {
+    for vault in [vault_a, vault_b] {
+        token::close_account(CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            CloseAccount {
+                account: vault.to_account_info(),
+                destination: maker.to_account_info(),
+                authority: swap.to_account_info(),
+            },
+            signer,
+        ))?;
+    }
+    Ok(())
+}
+
+// -------------------------------
+// Escrow State
+// -------------------------------
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EscrowState {
+    Pending,
+    BuyerDeposit,
+    Completed,
+    Cancelled,
+    Funded,
+    Accepted,
+    Disputed,
+}
+
+// -------------------------------
+// Pending amendment to escrow terms
+// -------------------------------
+#[account]
+pub struct Amendment {
+    pub escrow: Pubkey,
+    // Binds the amendment to one deal, since the escrow PDA is reused
+    pub escrow_created_at: i64,
+    pub proposer: Pubkey,
+    pub new_amount: u64,
+    pub new_deadline: i64,
+    pub seller_payout: u64,
+    pub bump: u8,
+}
+
+// -------------------------------
+// Dispute evidence (one PDA per party submission)
+// -------------------------------
+#[account]
+pub struct Evidence {
+    pub escrow: Pubkey,
+    pub party: Pubkey,
+    pub content_hash: [u8; 32],
+    pub uri: String,
+    pub submitted_at: i64,
+    pub bump: u8,
+}
+
+// -------------------------------
+// Escrow Config (global fee settings)
+// -------------------------------
+// `admin` also acts as the dispute arbiter
+#[account]
+pub struct EscrowConfig {
+    pub admin: Pubkey,
+    pub fee_bps: u16,
+    pub fee_recipient: Pubkey,
+    pub bump: u8,
+}
+
+// -------------------------------
+// Milestones
+// -------------------------------
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MilestoneStatus {
+    Pending,
+    Released,
+    Refunded,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct MilestoneInput {
+    pub amount: u64,
+    pub description_hash: [u8; 32],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct Milestone {
+    pub amount: u64,                // 8
+    pub description_hash: [u8; 32], // 32
+    pub status: MilestoneStatus,    // 1
+}
+
+// -------------------------------
+// Deposit log
+// -------------------------------
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct DepositEntry {
+    pub amount: u64,    // 8
+    pub timestamp: i64, // 8
+}
+
+// -------------------------------
+// Payout splits
+// -------------------------------
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct Payee {
+    pub recipient: Pubkey, // 32
+    pub share_bps: u16,    // 2
+}
+
+// -------------------------------
+// Escrow Account
+// -------------------------------
+#[account]
+pub struct EscrowAccount {
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub amount: u64,
+    pub state: EscrowState,
+    pub release_after: i64,
+    pub refund_after: i64,
+    pub milestones: Vec<Milestone>,
+    pub hashlock: [u8; 32],
+    pub timelock: i64,
+    pub agreed_amount: u64,
+    pub seller_payout: u64,
+    pub terms_hash: [u8; 32],
+    pub oracle: Pubkey,
+    pub condition: [u8; 32],
+    pub payees: Vec<Payee>,
+    pub disputed_at: i64,
+    pub buyer_evidence_count: u8,
+    pub seller_evidence_count: u8,
+    pub deposits: Vec<DepositEntry>,
+    // Deposits minus amounts returned to the buyer, capped at `agreed_amount`
+    pub net_deposited: u64,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub bump: u8,
+}
+
+impl EscrowAccount {
+    pub fn is_htlc(&self) -> bool {
+        self.hashlock != [0; 32]
+    }
+
+    pub fn has_oracle(&self) -> bool {
+        self.oracle != Pubkey::default()
+    }
+
+    // Portion of the escrowed amount owed to the seller on release
+    pub fn seller_share(&self) -> u64 {
+        self.amount.min(self.seller_payout)
+    }
+
+    pub fn milestone_total(&self) -> Result<u64> {
+        self.milestones.iter().try_fold(0u64, |total, m| {
+            total
+                .checked_add(m.amount)
+                .ok_or_else(|| error!(EscrowError::AmountOverflow))
+        })
+    }
+
+    pub fn all_milestones_settled(&self) -> bool {
+        self.milestones
+            .iter()
+            .all(|m| m.status != MilestoneStatus::Pending)
+    }
+
+    // Marks a pending milestone as settled and deducts it from the escrowed amount
+    fn take_milestone(&mut self, index: u8, status: MilestoneStatus) -> Result<u64> {
+        let milestone = self
+            .milestones
+            .get_mut(index as usize)
+            .ok_or(EscrowError::InvalidMilestone)?;
+        require!(
+            milestone.status == MilestoneStatus::Pending,
+            EscrowError::MilestoneAlreadySettled
+        );
+        require!(self.amount >= milestone.amount, EscrowError::InsufficientFunds);
+
+        milestone.status = status;
+        let amount = milestone.amount;
+        self.amount -= amount;
+        Ok(amount)
+    }
+
+    fn settle_pending_milestones(&mut self, status: MilestoneStatus) {
+        for milestone in self.milestones.iter_mut() {
+            if milestone.status == MilestoneStatus::Pending {
+                milestone.status = status;
+            }
+        }
+    }
+}
+
+// -------------------------------
+// Events
+// -------------------------------
+#[event]
+pub struct EscrowCreated {
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Deposited {
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub total: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Released {
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Refunded {
+    pub escrow: Pubkey,
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Disputed {
+    pub escrow: Pubkey,
+    pub raised_by: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Resolved {
+    pub escrow: Pubkey,
+    pub arbiter: Pubkey,
+    pub released_to_seller: bool,
+    pub amount: u64,
+    pub fee: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SecretRevealed {
+    pub escrow: Pubkey,
+    pub hashlock: [u8; 32],
+    pub preimage: [u8; 32],
+}
+
+// -------------------------------
+// Swap Account (two-sided token escrow)
+// -------------------------------
+#[account]
+pub struct SwapEscrow {
+    pub maker: Pubkey,
+    pub nonce: u64,
+    pub taker: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub maker_deposited: bool,
+    pub taker_deposited: bool,
+    pub state: EscrowState,
+    pub bump: u8,
+}
+
+// -------------------------------
+// Stream Account (linear vesting escrow)
+// -------------------------------
+#[account]
+pub struct StreamEscrow {
+    pub payer: Pubkey,
+    pub nonce: u64,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub withdrawn: u64,
+    pub start_ts: i64,
+    pub cliff_ts: i64,
+    pub end_ts: i64,
+    pub state: EscrowState,
+    pub bump: u8,
+}
+
+impl StreamEscrow {
+    // Nothing vests before the cliff; afterwards vesting is linear from `start_ts`
+    pub fn vested_amount(&self, now: i64) -> Result<u64> {
+        if now < self.cliff_ts {
+            return Ok(0);
+        }
+        if now >= self.end_ts {
+            return Ok(self.amount);
+        }
+
+        let elapsed = (now - self.start_ts) as u128;
+        let duration = (self.end_ts - self.start_ts) as u128;
+        let vested = (self.amount as u128)
+            .checked_mul(elapsed)
+            .ok_or(EscrowError::AmountOverflow)?
+            .checked_div(duration)
+            .ok_or(EscrowError::AmountOverflow)?;
+        Ok(vested as u64)
+    }
+}
+
+// -------------------------------
+// NFT Account (NFT-for-SOL escrow)
+// -------------------------------
+#[account]
+pub struct NftEscrow {
+    pub seller: Pubkey,
+    pub nonce: u64,
+    pub buyer: Pubkey,
+    pub nft_mint: Pubkey,
+    pub price: u64,
+    pub state: EscrowState,
+    pub bump: u8,
+}
+
+// -------------------------------
+// Accounts Context
+// -------------------------------
+#[derive(Accounts)]
+pub struct InitConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + 32 + 2 + 32 + 1,
+        seeds = [b"escrow_config"],
+        bump
+    )]
+    pub config: Account<'info, EscrowConfig>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::EscrowContract>,
+
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ EscrowError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow_config"],
+        bump = config.bump,
+        has_one = admin @ EscrowError::Unauthorized
+    )]
+    pub config: Account<'info, EscrowConfig>,
+}
+
+#[derive(Accounts)]
+pub struct InitEscrow<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    /// CHECK: Seller is just a pubkey
+    pub seller: SystemAccount<'info>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + 32 + 32 + 8 + 1 + 8 + 8 + (4 + MAX_MILESTONES * (8 + 32 + 1)) + 32 + 8 + 8 + 8 + 32 + 32 + 32 + (4 + MAX_PAYEES * (32 + 2)) + 8 + 1 + 1 + (4 + MAX_DEPOSITS * (8 + 8)) + 8 + 8 + 8 + 1,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetOracleCondition<'info> {
+    pub buyer: Signer<'info>,
+
+    #[account(mut, seeds = [b"escrow", buyer.key().as_ref()], bump = escrow_pda.bump)]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptEscrow<'info> {
+    pub seller: Signer<'info>,
+
+    /// CHECK: Only used to derive the escrow PDA
+    pub buyer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = buyer,
+        has_one = seller
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct DeclineEscrow<'info> {
+    pub seller: Signer<'info>,
+
+    #[account(mut)]
+    pub buyer: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = buyer,
+        has_one = seller,
+        close = buyer
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(mut, seeds = [b"escrow", buyer.key().as_ref()], bump = escrow_pda.bump)]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Release<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    /// CHECK: Seller receives SOL
+    #[account(mut)]
+    pub seller: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = seller
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(seeds = [b"escrow_config"], bump = config.bump)]
+    pub config: Account<'info, EscrowConfig>,
+
+    #[account(mut, address = config.fee_recipient @ EscrowError::InvalidFeeRecipient)]
+    pub fee_recipient: SystemAccount<'info>,
+
+    /// CHECK: Instructions sysvar, used to verify oracle attestations
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Refund<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(mut, seeds = [b"escrow", buyer.key().as_ref()], bump = escrow_pda.bump)]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    /// CHECK: Instructions sysvar, used to verify oracle attestations
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseEscrow<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        close = buyer
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+}
+
+// Permissionless: anyone may crank, funds always go to the stored seller
+#[derive(Accounts)]
+pub struct ClaimAfterDeadline<'info> {
+    pub caller: Signer<'info>,
+
+    /// CHECK: Derives the escrow PDA and receives any amended remainder
+    #[account(mut)]
+    pub buyer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub seller: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = buyer,
+        has_one = seller
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(seeds = [b"escrow_config"], bump = config.bump)]
+    pub config: Account<'info, EscrowConfig>,
+
+    #[account(mut, address = config.fee_recipient @ EscrowError::InvalidFeeRecipient)]
+    pub fee_recipient: SystemAccount<'info>,
+}
+
+// Permissionless: anyone may crank, funds always go to the stored buyer
+#[derive(Accounts)]
+pub struct ReclaimAfterDeadline<'info> {
+    pub caller: Signer<'info>,
+
+    #[account(mut)]
+    pub buyer: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = buyer
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAmendment<'info> {
+    #[account(
+        mut,
+        constraint = proposer.key() == escrow_pda.buyer || proposer.key() == escrow_pda.seller
+            @ EscrowError::Unauthorized
+    )]
+    pub proposer: Signer<'info>,
+
+    #[account(seeds = [b"escrow", escrow_pda.buyer.as_ref()], bump = escrow_pda.bump)]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + 32 + 8 + 32 + 8 + 8 + 8 + 1,
+        seeds = [
+            b"amendment",
+            escrow_pda.key().as_ref(),
+            &escrow_pda.created_at.to_le_bytes()
+        ],
+        bump
+    )]
+    pub amendment: Account<'info, Amendment>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAmendment<'info> {
+    #[account(
+        constraint = approver.key() == escrow_pda.buyer || approver.key() == escrow_pda.seller
+            @ EscrowError::Unauthorized
+    )]
+    pub approver: Signer<'info>,
+
+    #[account(mut)]
+    pub buyer: SystemAccount<'info>,
+
+    /// CHECK: Receives the amendment rent back
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = buyer
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"amendment",
+            escrow_pda.key().as_ref(),
+            &escrow_pda.created_at.to_le_bytes()
+        ],
+        bump = amendment.bump,
+        has_one = proposer,
+        close = proposer
+    )]
+    pub amendment: Account<'info, Amendment>,
+}
+
+#[derive(Accounts)]
+pub struct RejectAmendment<'info> {
+    #[account(
+        constraint = caller.key() == escrow_pda.buyer || caller.key() == escrow_pda.seller
+            @ EscrowError::Unauthorized
+    )]
+    pub caller: Signer<'info>,
+
+    /// CHECK: Receives the amendment rent back
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"escrow", escrow_pda.buyer.as_ref()], bump = escrow_pda.bump)]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"amendment",
+            escrow_pda.key().as_ref(),
+            &amendment.escrow_created_at.to_le_bytes()
+        ],
+        bump = amendment.bump,
+        has_one = proposer,
+        close = proposer
+    )]
+    pub amendment: Account<'info, Amendment>,
+}
+
+#[derive(Accounts)]
+pub struct RaiseDispute<'info> {
+    #[account(
+        constraint = party.key() == escrow_pda.buyer || party.key() == escrow_pda.seller
+            @ EscrowError::Unauthorized
+    )]
+    pub party: Signer<'info>,
+
+    #[account(mut, seeds = [b"escrow", escrow_pda.buyer.as_ref()], bump = escrow_pda.bump)]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u8)]
+pub struct SubmitEvidence<'info> {
+    #[account(
+        mut,
+        constraint = party.key() == escrow_pda.buyer || party.key() == escrow_pda.seller
+            @ EscrowError::Unauthorized
+    )]
+    pub party: Signer<'info>,
+
+    #[account(mut, seeds = [b"escrow", escrow_pda.buyer.as_ref()], bump = escrow_pda.bump)]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(
+        init,
+        payer = party,
+        space = 8 + 32 + 32 + 32 + (4 + MAX_EVIDENCE_URI_LEN) + 8 + 1,
+        seeds = [
+            b"evidence",
+            escrow_pda.key().as_ref(),
+            party.key().as_ref(),
+            &escrow_pda.disputed_at.to_le_bytes(),
+            &[index]
+        ],
+        bump
+    )]
+    pub evidence: Account<'info, Evidence>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    pub arbiter: Signer<'info>,
+
+    #[account(mut)]
+    pub buyer: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub seller: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = buyer,
+        has_one = seller
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(
+        seeds = [b"escrow_config"],
+        bump = config.bump,
+        constraint = config.admin == arbiter.key() @ EscrowError::Unauthorized
+    )]
+    pub config: Account<'info, EscrowConfig>,
+
+    #[account(mut, address = config.fee_recipient @ EscrowError::InvalidFeeRecipient)]
+    pub fee_recipient: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWithSecret<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    /// CHECK: Only used to derive the escrow PDA
+    pub buyer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrow", buyer.key().as_ref()],
+        bump = escrow_pda.bump,
+        has_one = buyer,
+        has_one = seller
+    )]
+    pub escrow_pda: Account<'info, EscrowAccount>,
+
+    #[account(seeds = [b"escrow_config"], bump = config.bump)]
+    pub config: Account<'info, EscrowConfig>,
+
+    #[account(mut, address = config.fee_recipient @ EscrowError::InvalidFeeRecipient)]
+    pub fee_recipient: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct InitSwap<'info> {
+    #[account(mut)]
+    pub maker: Signer<'info>,
+    /// CHECK: Taker is just a pubkey
+    pub taker: SystemAccount<'info>,
+
+    pub mint_a: Account<'info, Mint>,
+    pub mint_b: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = maker,
+        space = 8 + 32 + 8 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + 1 + 1,
+        seeds = [b"swap", maker.key().as_ref(), &nonce.to_le_bytes()],
+        bump
+    )]
+    pub swap_pda: Account<'info, SwapEscrow>,
+
+    #[account(
+        init,
+        payer = maker,
+        seeds = [b"swap_vault", swap_pda.key().as_ref(), mint_a.key().as_ref()],
+        bump,
+        token::mint = mint_a,
+        token::authority = swap_pda
+    )]
+    pub vault_a: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = maker,
+        seeds = [b"swap_vault", swap_pda.key().as_ref(), mint_b.key().as_ref()],
+        bump,
+        token::mint = mint_b,
+        token::authority = swap_pda
+    )]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct DepositSwap<'info> {
+    pub depositor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"swap", swap_pda.maker.as_ref(), &swap_pda.nonce.to_le_bytes()],
+        bump = swap_pda.bump
+    )]
+    pub swap_pda: Account<'info, SwapEscrow>,
+
+    #[account(mut, constraint = depositor_token.owner == depositor.key())]
+    pub depositor_token: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"swap_vault", swap_pda.key().as_ref(), depositor_token.mint.as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Exchange<'info> {
+    #[account(
+        constraint = caller.key() == swap_pda.maker || caller.key() == swap_pda.taker
+            @ EscrowError::Unauthorized
+    )]
+    pub caller: Signer<'info>,
+
+    /// CHECK: Receives the vault and swap account rent back
+    #[account(mut)]
+    pub maker: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"swap", maker.key().as_ref(), &swap_pda.nonce.to_le_bytes()],
+        bump = swap_pda.bump,
+        has_one = maker,
+        close = maker
+    )]
+    pub swap_pda: Account<'info, SwapEscrow>,
+
+    #[account(mut, seeds = [b"swap_vault", swap_pda.key().as_ref(), swap_pda.mint_a.as_ref()], bump)]
+    pub vault_a: Account<'info, TokenAccount>,
+
+    #[account(mut, seeds = [b"swap_vault", swap_pda.key().as_ref(), swap_pda.mint_b.as_ref()], bump)]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = taker_token_a.owner == swap_pda.taker,
+        constraint = taker_token_a.mint == swap_pda.mint_a
+    )]
+    pub taker_token_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_b.owner == swap_pda.maker,
+        constraint = maker_token_b.mint == swap_pda.mint_b
+    )]
+    pub maker_token_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelSwap<'info> {
+    #[account(
+        constraint = caller.key() == swap_pda.maker || caller.key() == swap_pda.taker
+            @ EscrowError::Unauthorized
+    )]
+    pub caller: Signer<'info>,
+
+    /// CHECK: Receives the vault and swap account rent back
+    #[account(mut)]
+    pub maker: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"swap", maker.key().as_ref(), &swap_pda.nonce.to_le_bytes()],
+        bump = swap_pda.bump,
+        has_one = maker,
+        close = maker
+    )]
+    pub swap_pda: Account<'info, SwapEscrow>,
+
+    #[account(mut, seeds = [b"swap_vault", swap_pda.key().as_ref(), swap_pda.mint_a.as_ref()], bump)]
+    pub vault_a: Account<'info, TokenAccount>,
+
+    #[account(mut, seeds = [b"swap_vault", swap_pda.key().as_ref(), swap_pda.mint_b.as_ref()], bump)]
+    pub vault_b: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = maker_token_a.owner == swap_pda.maker,
+        constraint = maker_token_a.mint == swap_pda.mint_a
+    )]
+    pub maker_token_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = taker_token_b.owner == swap_pda.taker,
+        constraint = taker_token_b.mint == swap_pda.mint_b
+    )]
+    pub taker_token_b: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CreateStream<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Recipient is just a pubkey
+    pub recipient: SystemAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 32 + 8 + 32 + 8 + 8 + 8 + 8 + 8 + 1 + 1,
+        seeds = [b"stream", payer.key().as_ref(), &nonce.to_le_bytes()],
+        bump
+    )]
+    pub stream_pda: Account<'info, StreamEscrow>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawVested<'info> {
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+
+    /// CHECK: Derives the stream PDA and receives its rent once fully withdrawn
+    #[account(mut)]
+    pub payer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stream", payer.key().as_ref(), &stream_pda.nonce.to_le_bytes()],
+        bump = stream_pda.bump,
+        has_one = payer,
+        has_one = recipient
+    )]
+    pub stream_pda: Account<'info, StreamEscrow>,
+}
+
+#[derive(Accounts)]
+pub struct CancelStream<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(mut)]
+    pub recipient: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"stream", payer.key().as_ref(), &stream_pda.nonce.to_le_bytes()],
+        bump = stream_pda.bump,
+        has_one = payer,
+        has_one = recipient,
+        close = payer
+    )]
+    pub stream_pda: Account<'info, StreamEscrow>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct ListNft<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    /// CHECK: Buyer is just a pubkey
+    pub buyer: SystemAccount<'info>,
+
+    #[account(constraint = nft_mint.decimals == 0 && nft_mint.supply == 1 @ EscrowError::NotAnNft)]
+    pub nft_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = seller_nft_account.owner == seller.key(),
+        constraint = seller_nft_account.mint == nft_mint.key()
+    )]
+    pub seller_nft_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + 32 + 8 + 32 + 32 + 8 + 1 + 1,
+        seeds = [b"nft_escrow", seller.key().as_ref(), &nonce.to_le_bytes()],
+        bump
+    )]
+    pub nft_escrow: Account<'info, NftEscrow>,
+
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"nft_vault", nft_escrow.key().as_ref()],
+        bump,
+        token::mint = nft_mint,
+        token::authority = nft_escrow
+    )]
+    pub nft_vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct DepositPayment<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_escrow", nft_escrow.seller.as_ref(), &nft_escrow.nonce.to_le_bytes()],
+        bump = nft_escrow.bump,
+        has_one = buyer
+    )]
+    pub nft_escrow: Account<'info, NftEscrow>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Settle<'info> {
+    #[account(
+        constraint = caller.key() == nft_escrow.seller || caller.key() == nft_escrow.buyer
+            @ EscrowError::Unauthorized
+    )]
+    pub caller: Signer<'info>,
+
+    #[account(mut)]
+    pub seller: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_escrow", seller.key().as_ref(), &nft_escrow.nonce.to_le_bytes()],
+        bump = nft_escrow.bump,
+        has_one = seller,
+        close = seller
+    )]
+    pub nft_escrow: Account<'info, NftEscrow>,
+
+    #[account(mut, seeds = [b"nft_vault", nft_escrow.key().as_ref()], bump)]
+    pub nft_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = buyer_nft_account.owner == nft_escrow.buyer,
+        constraint = buyer_nft_account.mint == nft_escrow.nft_mint
+    )]
+    pub buyer_nft_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RefundNft<'info> {
+    #[account(
+        constraint = caller.key() == nft_escrow.seller || caller.key() == nft_escrow.buyer
+            @ EscrowError::Unauthorized
+    )]
+    pub caller: Signer<'info>,
+
+    #[account(mut)]
+    pub seller: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub buyer: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"nft_escrow", seller.key().as_ref(), &nft_escrow.nonce.to_le_bytes()],
+        bump = nft_escrow.bump,
+        has_one = seller,
+        has_one = buyer,
+        close = seller
+    )]
+    pub nft_escrow: Account<'info, NftEscrow>,
+
+    #[account(mut, seeds = [b"nft_vault", nft_escrow.key().as_ref()], bump)]
+    pub nft_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = seller_nft_account.owner == nft_escrow.seller,
+        constraint = seller_nft_account.mint == nft_escrow.nft_mint
+    )]
+    pub seller_nft_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
 }
 
 // -------------------------------
@@ -180,4 +2540,78 @@ pub enum EscrowError {
     AmountOverflow,
     #[msg("Invalid state")]
     InvalidState,
+    #[msg("Invalid deadline")]
+    InvalidDeadline,
+    #[msg("Deadline not set")]
+    DeadlineNotSet,
+    #[msg("Deadline not reached")]
+    DeadlineNotReached,
+    #[msg("Too many milestones")]
+    TooManyMilestones,
+    #[msg("Invalid milestone index")]
+    InvalidMilestone,
+    #[msg("Milestone already released or refunded")]
+    MilestoneAlreadySettled,
+    #[msg("Agreed amount must equal milestone total")]
+    MilestoneTotalMismatch,
+    #[msg("Escrow has milestones, settle them individually")]
+    HasMilestones,
+    #[msg("Insufficient funds in escrow")]
+    InsufficientFunds,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Token mint does not match swap terms")]
+    MintMismatch,
+    #[msg("Side already deposited")]
+    AlreadyDeposited,
+    #[msg("Invalid hashlock")]
+    InvalidHashlock,
+    #[msg("Escrow is not hash time-locked")]
+    NotHtlc,
+    #[msg("Preimage does not match hashlock")]
+    InvalidPreimage,
+    #[msg("Timelock has expired")]
+    TimelockExpired,
+    #[msg("Timelock still active")]
+    TimelockActive,
+    #[msg("Escrow has not been accepted by the seller")]
+    NotAccepted,
+    #[msg("Deposit exceeds agreed amount")]
+    ExceedsAgreedAmount,
+    #[msg("Fee exceeds maximum")]
+    FeeTooHigh,
+    #[msg("Fee recipient does not match config")]
+    InvalidFeeRecipient,
+    #[msg("Invalid vesting schedule")]
+    InvalidSchedule,
+    #[msg("Nothing vested to withdraw")]
+    NothingVested,
+    #[msg("Invalid oracle")]
+    InvalidOracle,
+    #[msg("Missing oracle attestation")]
+    MissingOracleAttestation,
+    #[msg("Invalid oracle attestation")]
+    InvalidOracleAttestation,
+    #[msg("Too many payees")]
+    TooManyPayees,
+    #[msg("Payee shares must be non-zero and sum to 10000 bps")]
+    InvalidPayeeShares,
+    #[msg("Remaining accounts do not match stored payees")]
+    PayeeMismatch,
+    #[msg("Mint is not an NFT (supply 1, decimals 0)")]
+    NotAnNft,
+    #[msg("Evidence URI too long")]
+    UriTooLong,
+    #[msg("Evidence submission window has closed")]
+    EvidenceWindowClosed,
+    #[msg("Evidence submission window is still open")]
+    EvidenceWindowOpen,
+    #[msg("Invalid evidence index")]
+    InvalidEvidenceIndex,
+    #[msg("Too many deposits")]
+    TooManyDeposits,
+    #[msg("Escrow balance does not match recorded amount")]
+    BalanceMismatch,
+    #[msg("Escrow can only be settled with an oracle attestation")]
+    OracleRequired,
 }
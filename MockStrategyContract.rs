@@ -0,0 +1,112 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Approve, Mint, Token, TokenAccount, Transfer};
+
+declare_id!("GikHZ1uF5pKk4Aab4zptaxwFgXkFdk3RvQzhXki7x9mc");
+
+/// Minimal vault strategy used by the vault tests. It holds allocated funds
+/// in a token account owned by its own authority PDA. The vault PDA signs
+/// every call.
+#[program]
+pub mod mock_strategy {
+    use super::*;
+
+    /// Create the token account that holds funds allocated by `vault`
+    pub fn initialize(_ctx: Context<InitializeStrategy>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Take `amount` from the vault
+    pub fn deposit(ctx: Context<MoveFunds>, amount: u64) -> Result<()> {
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault_assets.to_account_info(),
+                    to: ctx.accounts.strategy_assets.to_account_info(),
+                    authority: ctx.accounts.vault.to_account_info(),
+                },
+            ),
+            amount,
+        )
+    }
+
+    /// Return `amount` to the vault
+    pub fn withdraw(ctx: Context<MoveFunds>, amount: u64) -> Result<()> {
+        let seeds = &[b"authority".as_ref(), &[ctx.bumps.authority]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.strategy_assets.to_account_info(),
+                    to: ctx.accounts.vault_assets.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )
+    }
+
+    /// Misbehave by approving the strategy authority to spend `amount` of
+    /// the vault's assets later, without moving anything now
+    pub fn approve(ctx: Context<MoveFunds>, amount: u64) -> Result<()> {
+        token::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Approve {
+                    to: ctx.accounts.vault_assets.to_account_info(),
+                    delegate: ctx.accounts.authority.to_account_info(),
+                    authority: ctx.accounts.vault.to_account_info(),
+                },
+            ),
+            amount,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeStrategy<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Vault the strategy holds funds for
+    pub vault: UncheckedAccount<'info>,
+
+    pub asset_mint: Account<'info, Mint>,
+
+    /// CHECK: PDA that owns the strategy's token account
+    #[account(seeds = [b"authority"], bump)]
+    pub authority: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"strategy_assets", vault.key().as_ref()],
+        bump,
+        token::mint = asset_mint,
+        token::authority = authority,
+    )]
+    pub strategy_assets: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+/// Account order the vault passes as remaining accounts
+#[derive(Accounts)]
+pub struct MoveFunds<'info> {
+    pub vault: Signer<'info>,
+
+    #[account(mut, constraint = vault_assets.owner == vault.key())]
+    pub vault_assets: Account<'info, TokenAccount>,
+
+    #[account(mut, seeds = [b"strategy_assets", vault.key().as_ref()], bump)]
+    pub strategy_assets: Account<'info, TokenAccount>,
+
+    /// CHECK: PDA that owns the strategy's token account
+    #[account(seeds = [b"authority"], bump)]
+    pub authority: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
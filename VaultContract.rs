@@ -1,257 +1,1804 @@
 use anchor_lang::prelude::*;
-use anchor_lang::system_program;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
 
 declare_id!("2Hzb64AtazgV7yxkxgVQjUgWnr7X1V5s9D55cSSgiJbY");
 
+/// Shares minted on the first deposit that are never redeemable, so the share
+/// price cannot be inflated by donating to a near-empty vault
+pub const MINIMUM_LOCKED_SHARES: u64 = 1_000;
+
+/// Upper bounds the owner can set fees to, in basis points
+pub const MAX_MANAGEMENT_FEE_BPS: u16 = 500;
+pub const MAX_PERFORMANCE_FEE_BPS: u16 = 3_000;
+pub const BPS_DENOMINATOR: u64 = 10_000;
+pub const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+/// Delay between announcing a transfer of vault assets and executing it,
+/// giving depositors time to exit first
+pub const TRANSFER_TIMELOCK: i64 = 7 * 24 * 60 * 60;
+
+pub const MAX_STRATEGIES: usize = 5;
+
+/// Minimum length of a withdrawal queue epoch
+pub const EPOCH_DURATION: i64 = 24 * 60 * 60;
+
+/// Fixed-point scale of the share price tracked by the high-water mark
+pub const PRICE_SCALE: u64 = 1_000_000_000;
+
 #[program]
 pub mod vault_contract {
     use super::*;
 
-    /// Initialize the shared vault
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
-        vault.owner = ctx.accounts.owner.key();
-        vault.total_deposited = 0;
-        vault.total_shares = 0;
-        vault.bump = ctx.bumps.vault;
-        
-        msg!("Vault initialized by owner: {}", vault.owner);
-        Ok(())
-    }
+    /// Initialize a vault for `asset_mint` owned by the signer.
+    /// SOL vaults use the wrapped SOL mint.
+    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.owner = ctx.accounts.owner.key();
+        vault.asset_mint = ctx.accounts.asset_mint.key();
+        vault.asset_vault = ctx.accounts.asset_vault.key();
+        vault.total_deposited = 0;
+        vault.total_shares = 0;
+        vault.share_mint = ctx.accounts.share_mint.key();
+        vault.management_fee_bps = 0;
+        vault.performance_fee_bps = 0;
+        vault.high_water_mark = PRICE_SCALE;
+        vault.last_fee_accrual = Clock::get()?.unix_timestamp;
+        vault.fee_shares = 0;
+        vault.pending_transfer_amount = 0;
+        vault.pending_transfer_destination = Pubkey::default();
+        vault.pending_transfer_eta = 0;
+        vault.strategies = Vec::new();
+        vault.total_allocated = 0;
+        vault.current_epoch = 0;
+        vault.epoch_started_at = Clock::get()?.unix_timestamp;
+        vault.epoch_queued_shares = 0;
+        vault.reserved_assets = 0;
+        vault.deposit_cap = u64::MAX;
+        vault.user_deposit_cap = 0;
+        vault.allowlist_root = [0u8; 32];
+        vault.guardian = ctx.accounts.owner.key();
+        vault.paused = false;
+        vault.bump = ctx.bumps.vault;
+
+        msg!(
+            "Vault for mint {} initialized by owner: {} with share mint {}",
+            vault.asset_mint,
+            vault.owner,
+            vault.share_mint
+        );
+        Ok(())
+    }
+
+    /// Open the depositor's position, which tracks their net deposits for
+    /// the per-user cap. Required before depositing or redeeming.
+    pub fn open_position(ctx: Context<OpenPosition>) -> Result<()> {
+        let position = &mut ctx.accounts.position;
+        position.owner = ctx.accounts.depositor.key();
+        position.vault = ctx.accounts.vault.key();
+        position.deposited = 0;
+        position.open_requests = 0;
+        position.bump = ctx.bumps.position;
+
+        msg!("Position opened for {}", position.owner);
+        Ok(())
+    }
+
+    /// Close the depositor's position and return its rent once they hold no
+    /// shares and have no queued withdrawal left to claim
+    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
+        require!(
+            ctx.accounts.depositor_shares.amount == 0 && ctx.accounts.position.open_requests == 0,
+            VaultError::PositionNotEmpty
+        );
+
+        // Rent is returned to the depositor by the `close` constraint
+        msg!("Position closed for {}", ctx.accounts.position.owner);
+        Ok(())
+    }
+
+    /// Deposit `amount` for shares. `proof` is only checked when the vault
+    /// has an allowlist and may be empty otherwise.
+    pub fn deposit(ctx: Context<Deposit>, amount: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        // Price shares against assets held before this deposit lands
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        accrue_fees(&mut ctx.accounts.vault, total_assets)?;
+
+        let (minted, user_shares) = deposit_shares(
+            &ctx.accounts.vault,
+            &ctx.accounts.position,
+            &proof,
+            total_assets,
+            amount,
+        )?;
+
+        let cpi_context = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.depositor_assets.to_account_info(),
+                to: ctx.accounts.asset_vault.to_account_info(),
+                authority: ctx.accounts.depositor.to_account_info(),
+            },
+        );
+        token::transfer(cpi_context, amount)?;
+
+        // Locked shares are counted in `total_shares` but never minted
+        let vault = &ctx.accounts.vault;
+        let seeds = &[
+            b"vault".as_ref(),
+            vault.asset_mint.as_ref(),
+            vault.owner.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let mint_context = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.share_mint.to_account_info(),
+                to: ctx.accounts.depositor_shares.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            signer,
+        );
+        token::mint_to(mint_context, user_shares)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.total_deposited = vault.total_deposited
+            .checked_add(amount)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        vault.total_shares = vault.total_shares
+            .checked_add(minted)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        let position = &mut ctx.accounts.position;
+        position.deposited = position.deposited
+            .checked_add(amount)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        msg!("Deposited {} tokens for {} shares", amount, user_shares);
+        Ok(())
+    }
+
+    /// Redeem every share the depositor holds
+    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
+        let shares = ctx.accounts.depositor_shares.amount;
+        require!(shares > 0, VaultError::NoDeposit);
+
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        accrue_fees(&mut ctx.accounts.vault, total_assets)?;
+
+        let assets = shares_to_assets(shares, total_assets, ctx.accounts.vault.total_shares)?;
+        redeem(ctx.accounts, shares, assets)
+    }
+
+    /// Redeem `shares`, paying out the assets they are worth rounded down
+    pub fn withdraw_shares(ctx: Context<Withdraw>, shares: u64) -> Result<()> {
+        require!(shares > 0, VaultError::InvalidAmount);
+        require!(
+            ctx.accounts.depositor_shares.amount >= shares,
+            VaultError::InsufficientShares
+        );
+
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        accrue_fees(&mut ctx.accounts.vault, total_assets)?;
+
+        let assets = shares_to_assets(shares, total_assets, ctx.accounts.vault.total_shares)?;
+        redeem(ctx.accounts, shares, assets)
+    }
+
+    /// Withdraw `amount` of assets, burning the shares needed rounded up
+    pub fn withdraw_assets(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        require!(amount > 0, VaultError::InvalidAmount);
+
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        accrue_fees(&mut ctx.accounts.vault, total_assets)?;
+
+        let shares = assets_to_shares_up(amount, total_assets, ctx.accounts.vault.total_shares)?;
+        require!(
+            ctx.accounts.depositor_shares.amount >= shares,
+            VaultError::InsufficientShares
+        );
+        redeem(ctx.accounts, shares, amount)
+    }
+
+    /// Update the vault's fees, settling what accrued under the old rates first
+    pub fn set_fees(
+        ctx: Context<SetFees>,
+        management_fee_bps: u16,
+        performance_fee_bps: u16,
+    ) -> Result<()> {
+        require!(
+            management_fee_bps <= MAX_MANAGEMENT_FEE_BPS
+                && performance_fee_bps <= MAX_PERFORMANCE_FEE_BPS,
+            VaultError::FeeTooHigh
+        );
+
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        let vault = &mut ctx.accounts.vault;
+        accrue_fees(vault, total_assets)?;
+
+        vault.management_fee_bps = management_fee_bps;
+        vault.performance_fee_bps = performance_fee_bps;
+
+        msg!(
+            "Fees set to {} bps management, {} bps performance",
+            management_fee_bps,
+            performance_fee_bps
+        );
+        Ok(())
+    }
+
+    /// Mint accrued fee shares to the owner
+    pub fn claim_fees(ctx: Context<ClaimFees>) -> Result<()> {
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        accrue_fees(&mut ctx.accounts.vault, total_assets)?;
+
+        let vault = &ctx.accounts.vault;
+        let fee_shares = vault.fee_shares;
+        require!(fee_shares > 0, VaultError::InvalidAmount);
+
+        let seeds = &[
+            b"vault".as_ref(),
+            vault.asset_mint.as_ref(),
+            vault.owner.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let mint_context = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.share_mint.to_account_info(),
+                to: ctx.accounts.owner_shares.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            signer,
+        );
+        token::mint_to(mint_context, fee_shares)?;
+
+        ctx.accounts.vault.fee_shares = 0;
+
+        msg!("Owner claimed {} fee shares", fee_shares);
+        Ok(())
+    }
+
+    /// Withdraw `amount` of accrued fees, redeeming unclaimed fee shares.
+    /// Depositor funds can only leave through an announced transfer.
+    pub fn owner_withdraw(ctx: Context<OwnerWithdraw>, amount: u64) -> Result<()> {
+        require!(amount > 0, VaultError::InvalidAmount);
+
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        accrue_fees(&mut ctx.accounts.vault, total_assets)?;
+
+        let shares = assets_to_shares_up(amount, total_assets, ctx.accounts.vault.total_shares)?;
+        require!(
+            shares <= ctx.accounts.vault.fee_shares,
+            VaultError::ExceedsAccruedFees
+        );
+        require!(
+            available_liquidity(&ctx.accounts.vault, &ctx.accounts.asset_vault) >= amount,
+            VaultError::InsufficientFunds
+        );
+
+        let vault = &ctx.accounts.vault;
+        let seeds = &[
+            b"vault".as_ref(),
+            vault.asset_mint.as_ref(),
+            vault.owner.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_context = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.asset_vault.to_account_info(),
+                to: ctx.accounts.owner_assets.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(cpi_context, amount)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.fee_shares -= shares;
+        vault.total_shares = vault.total_shares
+            .checked_sub(shares)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        msg!("Owner withdrew {} tokens of fees for {} shares", amount, shares);
+        Ok(())
+    }
+
+    /// Announce a transfer of vault assets to `destination`, executable
+    /// once `TRANSFER_TIMELOCK` has passed
+    pub fn announce_transfer(ctx: Context<AnnounceTransfer>, amount: u64) -> Result<()> {
+        require!(amount > 0, VaultError::InvalidAmount);
+
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.pending_transfer_eta == 0, VaultError::TransferPending);
+
+        let eta = Clock::get()?.unix_timestamp
+            .checked_add(TRANSFER_TIMELOCK)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        vault.pending_transfer_amount = amount;
+        vault.pending_transfer_destination = ctx.accounts.destination.key();
+        vault.pending_transfer_eta = eta;
+
+        msg!(
+            "Transfer of {} tokens to {} announced, executable at {}",
+            amount,
+            vault.pending_transfer_destination,
+            eta
+        );
+        Ok(())
+    }
+
+    pub fn execute_transfer(ctx: Context<ExecuteTransfer>) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        require!(vault.pending_transfer_eta != 0, VaultError::NoTransferPending);
+        require!(
+            Clock::get()?.unix_timestamp >= vault.pending_transfer_eta,
+            VaultError::TimelockNotElapsed
+        );
+
+        let amount = vault.pending_transfer_amount;
+        require!(
+            available_liquidity(&ctx.accounts.vault, &ctx.accounts.asset_vault) >= amount,
+            VaultError::InsufficientFunds
+        );
+
+        let seeds = &[
+            b"vault".as_ref(),
+            vault.asset_mint.as_ref(),
+            vault.owner.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_context = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.asset_vault.to_account_info(),
+                to: ctx.accounts.destination.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(cpi_context, amount)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.pending_transfer_amount = 0;
+        vault.pending_transfer_destination = Pubkey::default();
+        vault.pending_transfer_eta = 0;
+
+        msg!("Executed transfer of {} tokens", amount);
+        Ok(())
+    }
+
+    pub fn cancel_transfer(ctx: Context<CancelTransfer>) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        require!(vault.pending_transfer_eta != 0, VaultError::NoTransferPending);
+
+        vault.pending_transfer_amount = 0;
+        vault.pending_transfer_destination = Pubkey::default();
+        vault.pending_transfer_eta = 0;
+
+        msg!("Pending transfer cancelled");
+        Ok(())
+    }
+
+    /// Whitelist a strategy program. It can only receive funds once
+    /// `TRANSFER_TIMELOCK` has passed, so depositors can exit first.
+    pub fn add_strategy(ctx: Context<ManageStrategies>, program_id: Pubkey) -> Result<()> {
+        // The vault PDA signs strategy calls, so these could move its tokens
+        // or re-enter the vault directly
+        require!(
+            program_id != token::ID && program_id != crate::ID,
+            VaultError::StrategyNotAllowed
+        );
+
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            vault.strategies.len() < MAX_STRATEGIES,
+            VaultError::TooManyStrategies
+        );
+        require!(
+            !vault.strategies.iter().any(|s| s.program_id == program_id),
+            VaultError::StrategyExists
+        );
+
+        let active_after = Clock::get()?.unix_timestamp
+            .checked_add(TRANSFER_TIMELOCK)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        vault.strategies.push(Strategy {
+            program_id,
+            allocated: 0,
+            active_after,
+        });
+
+        msg!("Strategy {} added, active after {}", program_id, active_after);
+        Ok(())
+    }
+
+    pub fn remove_strategy(ctx: Context<ManageStrategies>, index: u8) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let strategy = vault
+            .strategies
+            .get(index as usize)
+            .ok_or(VaultError::InvalidStrategy)?;
+        require!(strategy.allocated == 0, VaultError::StrategyHasFunds);
+
+        let removed = vault.strategies.remove(index as usize);
+
+        msg!("Strategy {} removed", removed.program_id);
+        Ok(())
+    }
+
+    /// Move up to `amount` into strategy `index` by calling it with `data`.
+    /// Accounts for the call are passed as remaining accounts.
+    pub fn allocate<'info>(
+        ctx: Context<'_, '_, '_, 'info, Strategize<'info>>,
+        index: u8,
+        amount: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        require!(amount > 0, VaultError::InvalidAmount);
+        require!(
+            available_liquidity(&ctx.accounts.vault, &ctx.accounts.asset_vault) >= amount,
+            VaultError::InsufficientFunds
+        );
+
+        let before = call_strategy(ctx.accounts, ctx.remaining_accounts, index, data)?;
+
+        let moved = before.saturating_sub(ctx.accounts.asset_vault.amount);
+        require!(moved <= amount, VaultError::AllocationExceeded);
+        require!(
+            ctx.accounts.asset_vault.amount >= ctx.accounts.vault.reserved_assets,
+            VaultError::AllocationExceeded
+        );
+
+        let vault = &mut ctx.accounts.vault;
+        let strategy = &mut vault.strategies[index as usize];
+        strategy.allocated = strategy.allocated
+            .checked_add(moved)
+            .ok_or(VaultError::AmountOverflow)?;
+        vault.total_allocated = vault.total_allocated
+            .checked_add(moved)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        msg!("Allocated {} tokens to strategy {}", moved, index);
+        Ok(())
+    }
+
+    /// Pull `amount` of book value out of strategy `index`. Whatever the
+    /// strategy returns replaces it, realizing any gain or loss.
+    pub fn deallocate<'info>(
+        ctx: Context<'_, '_, '_, 'info, Strategize<'info>>,
+        index: u8,
+        amount: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        require!(amount > 0, VaultError::InvalidAmount);
+
+        let before = call_strategy(ctx.accounts, ctx.remaining_accounts, index, data)?;
+        require!(
+            amount <= ctx.accounts.vault.strategies[index as usize].allocated,
+            VaultError::InsufficientFunds
+        );
+        require!(
+            ctx.accounts.asset_vault.amount >= before,
+            VaultError::VaultOutflow
+        );
+        let returned = ctx.accounts.asset_vault.amount - before;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.strategies[index as usize].allocated -= amount;
+        vault.total_allocated = vault.total_allocated.saturating_sub(amount);
+
+        if returned >= amount {
+            msg!(
+                "Deallocated {} tokens from strategy {} (gain: {})",
+                returned,
+                index,
+                returned - amount
+            );
+        } else {
+            msg!(
+                "Deallocated {} tokens from strategy {} (loss: {})",
+                returned,
+                index,
+                amount - returned
+            );
+        }
+        Ok(())
+    }
+
+    /// Collect yield from strategy `index` into the vault without touching
+    /// its allocation
+    pub fn harvest<'info>(
+        ctx: Context<'_, '_, '_, 'info, Strategize<'info>>,
+        index: u8,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let before = call_strategy(ctx.accounts, ctx.remaining_accounts, index, data)?;
+        require!(
+            ctx.accounts.asset_vault.amount >= before,
+            VaultError::HarvestLoss
+        );
+        let harvested = ctx.accounts.asset_vault.amount - before;
+
+        msg!("Harvested {} tokens from strategy {}", harvested, index);
+        Ok(())
+    }
+
+    /// Set the total deposit cap, the per-user cap (0 for none) and the
+    /// allowlist merkle root (all zeroes for an open vault)
+    pub fn set_limits(
+        ctx: Context<ConfigureVault>,
+        deposit_cap: u64,
+        user_deposit_cap: u64,
+        allowlist_root: [u8; 32],
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.deposit_cap = deposit_cap;
+        vault.user_deposit_cap = user_deposit_cap;
+        vault.allowlist_root = allowlist_root;
+
+        msg!(
+            "Limits set: deposit cap {}, user cap {}",
+            deposit_cap,
+            user_deposit_cap
+        );
+        Ok(())
+    }
+
+    pub fn set_guardian(ctx: Context<ConfigureVault>, guardian: Pubkey) -> Result<()> {
+        ctx.accounts.vault.guardian = guardian;
+
+        msg!("Guardian set to {}", guardian);
+        Ok(())
+    }
+
+    /// Pause or resume deposits. Withdrawals stay open while paused.
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        ctx.accounts.vault.paused = paused;
+
+        msg!("Vault deposits {}", if paused { "paused" } else { "resumed" });
+        Ok(())
+    }
+
+    /// Shares `deposit` would currently mint to the position's owner for
+    /// `amount`, failing wherever `deposit` would
+    pub fn preview_deposit(
+        ctx: Context<PreviewDeposit>,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<u64> {
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        let vault = accrued_view(&ctx.accounts.vault, total_assets)?;
+
+        let (_, user_shares) =
+            deposit_shares(&vault, &ctx.accounts.position, &proof, total_assets, amount)?;
+        Ok(user_shares)
+    }
+
+    /// Shares `withdraw_assets` would currently burn for `amount`
+    pub fn preview_withdraw(ctx: Context<VaultView>, amount: u64) -> Result<u64> {
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        let vault = accrued_view(&ctx.accounts.vault, total_assets)?;
+
+        assets_to_shares_up(amount, total_assets, vault.total_shares)
+    }
+
+    pub fn total_assets(ctx: Context<VaultView>) -> Result<u64> {
+        Ok(vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault))
+    }
+
+    /// Shares worth `assets` at the current share price, rounded down
+    pub fn convert_to_shares(ctx: Context<VaultView>, assets: u64) -> Result<u64> {
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        let vault = accrued_view(&ctx.accounts.vault, total_assets)?;
+
+        if vault.total_shares == 0 {
+            return Ok(assets);
+        }
+        assets_to_shares(assets, total_assets, vault.total_shares)
+    }
+
+    /// Queue `shares` for redemption at the close of the current epoch.
+    /// The shares are burned now and stay in `total_shares` until then.
+    pub fn request_withdrawal(ctx: Context<RequestWithdrawal>, shares: u64) -> Result<()> {
+        require!(shares > 0, VaultError::InvalidAmount);
+        require!(
+            ctx.accounts.depositor_shares.amount >= shares,
+            VaultError::InsufficientShares
+        );
+
+        let burn_context = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: ctx.accounts.share_mint.to_account_info(),
+                from: ctx.accounts.depositor_shares.to_account_info(),
+                authority: ctx.accounts.depositor.to_account_info(),
+            },
+        );
+        token::burn(burn_context, shares)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.epoch_queued_shares = vault.epoch_queued_shares
+            .checked_add(shares)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        let request = &mut ctx.accounts.request;
+        request.owner = ctx.accounts.depositor.key();
+        request.vault = vault.key();
+        request.epoch = vault.current_epoch;
+        request.shares = shares;
+        request.bump = ctx.bumps.request;
+
+        let position = &mut ctx.accounts.position;
+        position.open_requests = position.open_requests
+            .checked_add(1)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        msg!("Queued {} shares for epoch {}", shares, request.epoch);
+        Ok(())
+    }
+
+    /// Permissionless crank converting the epoch's queued shares at the
+    /// current share price and reserving the assets for claims. The payer
+    /// gets the record's rent back once every claim has been paid.
+    pub fn close_epoch(ctx: Context<CloseEpoch>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now >= ctx.accounts.vault.epoch_started_at + EPOCH_DURATION,
+            VaultError::EpochNotOver
+        );
+
+        let total_assets = vault_assets(&ctx.accounts.vault, &ctx.accounts.asset_vault);
+        accrue_fees(&mut ctx.accounts.vault, total_assets)?;
+
+        let vault = &mut ctx.accounts.vault;
+        let shares = vault.epoch_queued_shares;
+        let assets = if shares > 0 {
+            shares_to_assets(shares, total_assets, vault.total_shares)?
+        } else {
+            0
+        };
+
+        let record = &mut ctx.accounts.epoch_record;
+        record.vault = vault.key();
+        record.epoch = vault.current_epoch;
+        record.shares = shares;
+        record.assets = assets;
+        record.claimed_shares = 0;
+        record.claimed_assets = 0;
+        record.payer = ctx.accounts.payer.key();
+        record.bump = ctx.bumps.epoch_record;
+
+        vault.total_shares = vault.total_shares
+            .checked_sub(shares)
+            .ok_or(VaultError::AmountOverflow)?;
+        vault.reserved_assets = vault.reserved_assets
+            .checked_add(assets)
+            .ok_or(VaultError::AmountOverflow)?;
+        vault.total_deposited = vault.total_deposited.saturating_sub(assets);
+        vault.epoch_queued_shares = 0;
+        vault.current_epoch += 1;
+        vault.epoch_started_at = now;
+
+        msg!(
+            "Closed epoch {}: {} shares converted to {} tokens",
+            record.epoch,
+            shares,
+            assets
+        );
+
+        // Nothing will ever be claimed from an empty epoch
+        if shares == 0 {
+            ctx.accounts.epoch_record.close(ctx.accounts.payer.to_account_info())?;
+        }
+        Ok(())
+    }
+
+    /// Pay out a queued withdrawal once its epoch has closed and the vault
+    /// holds enough liquid assets
+    pub fn claim_withdrawal(ctx: Context<ClaimWithdrawal>) -> Result<()> {
+        let record = &ctx.accounts.epoch_record;
+        let payout = shares_to_assets(ctx.accounts.request.shares, record.assets, record.shares)?;
+        require!(
+            ctx.accounts.asset_vault.amount >= payout,
+            VaultError::InsufficientLiquidity
+        );
+
+        let vault = &ctx.accounts.vault;
+        let seeds = &[
+            b"vault".as_ref(),
+            vault.asset_mint.as_ref(),
+            vault.owner.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_context = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.asset_vault.to_account_info(),
+                to: ctx.accounts.depositor_assets.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            signer,
+        );
+        token::transfer(cpi_context, payout)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.reserved_assets = vault.reserved_assets.saturating_sub(payout);
+        let position = &mut ctx.accounts.position;
+        position.deposited = position.deposited.saturating_sub(payout);
+        position.open_requests = position.open_requests.saturating_sub(1);
+
+        let record = &mut ctx.accounts.epoch_record;
+        record.claimed_shares = record.claimed_shares
+            .checked_add(ctx.accounts.request.shares)
+            .ok_or(VaultError::AmountOverflow)?;
+        record.claimed_assets = record.claimed_assets
+            .checked_add(payout)
+            .ok_or(VaultError::AmountOverflow)?;
+
+        msg!(
+            "Claimed {} tokens for {} shares queued in epoch {}",
+            payout,
+            ctx.accounts.request.shares,
+            ctx.accounts.request.epoch
+        );
+
+        // Once every claim is paid, rounding dust goes back to depositors
+        // and the crank payer gets the record's rent back
+        if record.claimed_shares == record.shares {
+            let dust = record.assets.saturating_sub(record.claimed_assets);
+            vault.reserved_assets = vault.reserved_assets.saturating_sub(dust);
+            ctx.accounts.epoch_record.close(ctx.accounts.epoch_payer.to_account_info())?;
+        }
+        Ok(())
+    }
+}
+
+/// Check a merkle proof that `depositor` is a leaf under `root`, hashing
+/// sibling pairs in sorted order
+fn verify_allowlist(root: &[u8; 32], depositor: &Pubkey, proof: &[[u8; 32]]) -> bool {
+    let mut node = hashv(&[depositor.as_ref()]).to_bytes();
+    for sibling in proof {
+        node = if node <= *sibling {
+            hashv(&[&node, sibling]).to_bytes()
+        } else {
+            hashv(&[sibling, &node]).to_bytes()
+        };
+    }
+    node == *root
+}
+
+/// Check a deposit of `amount` by the owner of `position` against the
+/// vault's limits, returning the shares to add to the supply and the
+/// shares to mint to the depositor. `vault` must have fees accrued.
+fn deposit_shares(
+    vault: &Vault,
+    position: &UserPosition,
+    proof: &[[u8; 32]],
+    total_assets: u64,
+    amount: u64,
+) -> Result<(u64, u64)> {
+    require!(amount > 0, VaultError::InvalidAmount);
+    require!(!vault.paused, VaultError::VaultPaused);
+
+    if vault.allowlist_root != [0u8; 32] {
+        require!(
+            verify_allowlist(&vault.allowlist_root, &position.owner, proof),
+            VaultError::NotAllowlisted
+        );
+    }
+
+    require!(
+        total_assets
+            .checked_add(amount)
+            .is_some_and(|assets| assets <= vault.deposit_cap),
+        VaultError::DepositCapExceeded
+    );
+    if vault.user_deposit_cap > 0 {
+        require!(
+            position
+                .deposited
+                .checked_add(amount)
+                .is_some_and(|assets| assets <= vault.user_deposit_cap),
+            VaultError::UserCapExceeded
+        );
+    }
+
+    let (minted, user_shares) = if vault.total_shares == 0 {
+        require!(amount > MINIMUM_LOCKED_SHARES, VaultError::DepositTooSmall);
+        (amount, amount - MINIMUM_LOCKED_SHARES)
+    } else {
+        let shares = assets_to_shares(amount, total_assets, vault.total_shares)?;
+        (shares, shares)
+    };
+    require!(user_shares > 0, VaultError::ZeroShares);
+
+    Ok((minted, user_shares))
+}
+
+/// Call a whitelisted strategy program with the vault PDA as signer
+fn invoke_strategy<'info>(
+    vault: &Account<'info, Vault>,
+    strategy_program: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    data: Vec<u8>,
+) -> Result<()> {
+    let vault_key = vault.key();
+    let accounts = remaining_accounts
+        .iter()
+        .map(|account| AccountMeta {
+            pubkey: account.key(),
+            is_signer: account.is_signer || account.key() == vault_key,
+            is_writable: account.is_writable,
+        })
+        .collect();
+
+    let instruction = Instruction {
+        program_id: strategy_program.key(),
+        accounts,
+        data,
+    };
+
+    let mut account_infos = remaining_accounts.to_vec();
+    account_infos.push(vault.to_account_info());
+    account_infos.push(strategy_program.clone());
+
+    let seeds = &[
+        b"vault".as_ref(),
+        vault.asset_mint.as_ref(),
+        vault.owner.as_ref(),
+        &[vault.bump],
+    ];
+    invoke_signed(&instruction, &account_infos, &[&seeds[..]])?;
+    Ok(())
+}
+
+/// Call active strategy `index` and reload the vault's token accounts,
+/// returning the asset balance from before the call. The call must not
+/// change the share supply, and since the vault PDA signs it, must leave
+/// no delegate or changed authority behind on the vault's token accounts.
+fn call_strategy<'info>(
+    accounts: &mut Strategize<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    index: u8,
+    data: Vec<u8>,
+) -> Result<u64> {
+    let strategy = accounts.vault
+        .strategies
+        .get(index as usize)
+        .ok_or(VaultError::InvalidStrategy)?;
+    require!(
+        strategy.program_id == accounts.strategy_program.key(),
+        VaultError::InvalidStrategy
+    );
+    require!(
+        Clock::get()?.unix_timestamp >= strategy.active_after,
+        VaultError::TimelockNotElapsed
+    );
+
+    let before = accounts.asset_vault.amount;
+    let supply = accounts.share_mint.supply;
+    invoke_strategy(
+        &accounts.vault,
+        &accounts.strategy_program,
+        remaining_accounts,
+        data,
+    )?;
+    accounts.asset_vault.reload()?;
+    accounts.share_mint.reload()?;
+
+    require!(
+        accounts.share_mint.supply == supply,
+        VaultError::ShareSupplyChanged
+    );
+
+    let vault_key = accounts.vault.key();
+    let asset_vault = &accounts.asset_vault;
+    require!(
+        asset_vault.owner == vault_key
+            && asset_vault.delegate.is_none()
+            && asset_vault.close_authority.is_none()
+            && !asset_vault.is_frozen(),
+        VaultError::VaultAuthorityChanged
+    );
+    require!(
+        accounts.share_mint.mint_authority == COption::Some(vault_key),
+        VaultError::VaultAuthorityChanged
+    );
+    Ok(before)
+}
+
+/// Burn `shares` from the depositor and pay out `assets`. The share account
+/// is left open; the depositor can close it themselves once empty.
+fn redeem(accounts: &mut Withdraw, shares: u64, assets: u64) -> Result<()> {
+    require!(assets > 0, VaultError::ZeroAssets);
+    require!(
+        available_liquidity(&accounts.vault, &accounts.asset_vault) >= assets,
+        VaultError::InsufficientFunds
+    );
+
+    let burn_context = CpiContext::new(
+        accounts.token_program.to_account_info(),
+        Burn {
+            mint: accounts.share_mint.to_account_info(),
+            from: accounts.depositor_shares.to_account_info(),
+            authority: accounts.depositor.to_account_info(),
+        },
+    );
+    token::burn(burn_context, shares)?;
+
+    let vault = &accounts.vault;
+    let seeds = &[
+        b"vault".as_ref(),
+        vault.asset_mint.as_ref(),
+        vault.owner.as_ref(),
+        &[vault.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_context = CpiContext::new_with_signer(
+        accounts.token_program.to_account_info(),
+        Transfer {
+            from: accounts.asset_vault.to_account_info(),
+            to: accounts.depositor_assets.to_account_info(),
+            authority: vault.to_account_info(),
+        },
+        signer,
+    );
+    token::transfer(cpi_context, assets)?;
+
+    let vault = &mut accounts.vault;
+    vault.total_shares = vault.total_shares
+        .checked_sub(shares)
+        .ok_or(VaultError::AmountOverflow)?;
+
+    vault.total_deposited = vault.total_deposited.saturating_sub(assets);
+    accounts.position.deposited = accounts.position.deposited.saturating_sub(assets);
+
+    msg!("Withdrawn {} tokens for {} shares", assets, shares);
+
+    Ok(())
+}
+
+/// Charge management and performance fees accrued since the last call by
+/// adding fee shares to the supply, to be minted to the owner on claim
+fn accrue_fees(vault: &mut Vault, total_assets: u64) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let elapsed = now.saturating_sub(vault.last_fee_accrual).max(0) as u128;
+    vault.last_fee_accrual = now;
+
+    if vault.total_shares == 0 || total_assets == 0 {
+        return Ok(());
+    }
+
+    let assets = total_assets as u128;
+    let supply = vault.total_shares as u128;
+
+    let management_fee = assets
+        .checked_mul(vault.management_fee_bps as u128)
+        .ok_or(VaultError::AmountOverflow)?
+        .checked_mul(elapsed)
+        .ok_or(VaultError::AmountOverflow)?
+        / (BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128);
+
+    // Performance fee is only charged on share price gains above the high-water mark
+    let price = assets
+        .checked_mul(PRICE_SCALE as u128)
+        .ok_or(VaultError::AmountOverflow)?
+        / supply;
+    let high_water_mark = vault.high_water_mark as u128;
+    let performance_fee = if price > high_water_mark {
+        (price - high_water_mark)
+            .checked_mul(supply)
+            .ok_or(VaultError::AmountOverflow)?
+            .checked_mul(vault.performance_fee_bps as u128)
+            .ok_or(VaultError::AmountOverflow)?
+            / (PRICE_SCALE as u128 * BPS_DENOMINATOR as u128)
+    } else {
+        0
+    };
+
+    let fee_assets = (management_fee + performance_fee).min(assets - 1);
+    if fee_assets > 0 {
+        // Mint enough shares that the owner's claim equals `fee_assets` afterwards
+        let fee_shares = fee_assets
+            .checked_mul(supply)
+            .ok_or(VaultError::AmountOverflow)?
+            / (assets - fee_assets);
+        require!(fee_shares <= u64::MAX as u128, VaultError::AmountOverflow);
+
+        vault.total_shares = vault.total_shares
+            .checked_add(fee_shares as u64)
+            .ok_or(VaultError::AmountOverflow)?;
+        vault.fee_shares = vault.fee_shares
+            .checked_add(fee_shares as u64)
+            .ok_or(VaultError::AmountOverflow)?;
+    }
+
+    // Raise the mark even when no fee was charged (e.g. a 0% performance fee),
+    // so gains made then are not charged later
+    let new_price = assets * PRICE_SCALE as u128 / vault.total_shares as u128;
+    if new_price > high_water_mark && new_price <= u64::MAX as u128 {
+        vault.high_water_mark = new_price as u64;
+    }
+
+    Ok(())
+}
+
+/// Copy of the vault with pending fees accrued, as the next mutating
+/// instruction would price shares against
+fn accrued_view(vault: &Vault, total_assets: u64) -> Result<Vault> {
+    let mut vault = vault.clone();
+    accrue_fees(&mut vault, total_assets)?;
+    Ok(vault)
+}
+
+/// Assets `shares` are worth, rounded down in the vault's favor
+fn shares_to_assets(shares: u64, total_assets: u64, total_shares: u64) -> Result<u64> {
+    let assets = (shares as u128)
+        .checked_mul(total_assets as u128)
+        .ok_or(VaultError::AmountOverflow)?
+        .checked_div(total_shares as u128)
+        .ok_or(VaultError::DivisionByZero)?;
+
+    require!(assets <= u64::MAX as u128, VaultError::AmountOverflow);
+    Ok(assets as u64)
+}
+
+/// Shares worth `assets`, rounded down in the vault's favor
+fn assets_to_shares(assets: u64, total_assets: u64, total_shares: u64) -> Result<u64> {
+    let shares = (assets as u128)
+        .checked_mul(total_shares as u128)
+        .ok_or(VaultError::AmountOverflow)?
+        .checked_div(total_assets as u128)
+        .ok_or(VaultError::DivisionByZero)?;
+
+    require!(shares <= u64::MAX as u128, VaultError::AmountOverflow);
+    Ok(shares as u64)
+}
+
+/// Shares needed to withdraw `assets`, rounded up in the vault's favor
+fn assets_to_shares_up(assets: u64, total_assets: u64, total_shares: u64) -> Result<u64> {
+    require!(total_assets > 0, VaultError::DivisionByZero);
+    let shares = (assets as u128)
+        .checked_mul(total_shares as u128)
+        .ok_or(VaultError::AmountOverflow)?
+        .checked_add(total_assets as u128 - 1)
+        .ok_or(VaultError::AmountOverflow)?
+        / total_assets as u128;
+
+    require!(shares <= u64::MAX as u128, VaultError::AmountOverflow);
+    Ok(shares as u64)
+}
+
+/// Underlying tokens backing shares: held by the vault plus deployed to
+/// strategies, less what is reserved for closed withdrawal epochs
+fn vault_assets(vault: &Vault, asset_vault: &TokenAccount) -> u64 {
+    asset_vault.amount
+        .saturating_add(vault.total_allocated)
+        .saturating_sub(vault.reserved_assets)
+}
+
+/// Tokens the vault can pay out now without touching reserved claims
+fn available_liquidity(vault: &Vault, asset_vault: &TokenAccount) -> u64 {
+    asset_vault.amount.saturating_sub(vault.reserved_assets)
+}
+
+#[account]
+pub struct Vault {
+    pub owner: Pubkey,
+    pub asset_mint: Pubkey,
+    pub asset_vault: Pubkey,
+    pub total_deposited: u64,
+    pub total_shares: u64,
+    pub share_mint: Pubkey,
+    pub management_fee_bps: u16,
+    pub performance_fee_bps: u16,
+    /// Highest share price (scaled by `PRICE_SCALE`) performance fees were charged at
+    pub high_water_mark: u64,
+    pub last_fee_accrual: i64,
+    /// Fee shares counted in `total_shares` but not yet minted to the owner
+    pub fee_shares: u64,
+    /// Announced transfer of vault assets; `pending_transfer_eta` is 0 when none
+    pub pending_transfer_amount: u64,
+    pub pending_transfer_destination: Pubkey,
+    pub pending_transfer_eta: i64,
+    pub strategies: Vec<Strategy>,
+    /// Book value of assets deployed across all strategies
+    pub total_allocated: u64,
+    pub current_epoch: u64,
+    pub epoch_started_at: i64,
+    /// Shares queued for withdrawal in the current epoch, still in `total_shares`
+    pub epoch_queued_shares: u64,
+    /// Assets owed to queued withdrawals from closed epochs
+    pub reserved_assets: u64,
+    pub deposit_cap: u64,
+    /// Maximum net deposits per depositor, 0 when uncapped
+    pub user_deposit_cap: u64,
+    /// Merkle root of permitted depositors, all zeroes when open to anyone
+    pub allowlist_root: [u8; 32],
+    /// Can pause deposits alongside the owner
+    pub guardian: Pubkey,
+    pub paused: bool,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Strategy {
+    pub program_id: Pubkey,
+    pub allocated: u64,
+    pub active_after: i64,
+}
+
+/// Net assets a depositor has put in, checked against `user_deposit_cap`.
+/// Shares bought elsewhere do not count towards it.
+#[account]
+pub struct UserPosition {
+    pub owner: Pubkey,
+    pub vault: Pubkey,
+    pub deposited: u64,
+    /// Queued withdrawals not yet claimed
+    pub open_requests: u32,
+    pub bump: u8,
+}
+
+#[account]
+pub struct WithdrawalRequest {
+    pub owner: Pubkey,
+    pub vault: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub bump: u8,
+}
+
+#[account]
+pub struct EpochRecord {
+    pub vault: Pubkey,
+    pub epoch: u64,
+    pub shares: u64,
+    pub assets: u64,
+    /// Shares and assets paid out so far by `claim_withdrawal`
+    pub claimed_shares: u64,
+    pub claimed_assets: u64,
+    /// Crank that paid the record's rent, refunded when it closes
+    pub payer: Pubkey,
+    pub bump: u8,
+}
+
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub asset_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + 32 + 32 + 32 + 8 + 8 + 32 + 2 + 2 + 8 + 8 + 8 + 8 + 32 + 8
+            + 4 + MAX_STRATEGIES * (32 + 8 + 8) + 8 + 8 + 8 + 8 + 8
+            + 8 + 8 + 32 + 32 + 1 + 1,
+        seeds = [b"vault", asset_mint.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// Token account holding the vault's underlying assets
+    #[account(
+        init,
+        payer = owner,
+        seeds = [b"vault_assets", vault.key().as_ref()],
+        bump,
+        token::mint = asset_mint,
+        token::authority = vault,
+    )]
+    pub asset_vault: Account<'info, TokenAccount>,
+
+    /// Share token, same decimals as the underlying asset
+    #[account(
+        init,
+        payer = owner,
+        seeds = [b"share_mint", vault.key().as_ref()],
+        bump,
+        mint::decimals = asset_mint.decimals,
+        mint::authority = vault,
+    )]
+    pub share_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct OpenPosition<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = depositor,
+        space = 8 + 32 + 32 + 8 + 4 + 1,
+        seeds = [b"position", vault.key().as_ref(), depositor.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePosition<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(address = vault.share_mint)]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(
+        constraint = depositor_shares.mint == share_mint.key(),
+        constraint = depositor_shares.owner == depositor.key()
+    )]
+    pub depositor_shares: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"position", vault.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump,
+        close = depositor
+    )]
+    pub position: Account<'info, UserPosition>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = depositor_assets.mint == vault.asset_mint,
+        constraint = depositor_assets.owner == depositor.key()
+    )]
+    pub depositor_assets: Account<'info, TokenAccount>,
+
+    #[account(mut, address = vault.share_mint)]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = depositor_shares.mint == share_mint.key(),
+        constraint = depositor_shares.owner == depositor.key()
+    )]
+    pub depositor_shares: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"position", vault.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = depositor_assets.mint == vault.asset_mint,
+        constraint = depositor_assets.owner == depositor.key()
+    )]
+    pub depositor_assets: Account<'info, TokenAccount>,
+
+    #[account(mut, address = vault.share_mint)]
+    pub share_mint: Account<'info, Mint>,
 
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        require!(amount > 0, VaultError::InvalidAmount);
+    #[account(
+        mut,
+        constraint = depositor_shares.mint == share_mint.key(),
+        constraint = depositor_shares.owner == depositor.key()
+    )]
+    pub depositor_shares: Account<'info, TokenAccount>,
 
-        let cpi_context = CpiContext::new(
-            ctx.accounts.system_program.to_account_info(),
-            system_program::Transfer {
-                from: ctx.accounts.depositor.to_account_info(),
-                to: ctx.accounts.vault.to_account_info(),
-            },
-        );
-        system_program::transfer(cpi_context, amount)?;
+    #[account(
+        mut,
+        seeds = [b"position", vault.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
 
-        let vault = &mut ctx.accounts.vault;
-        vault.total_deposited = vault.total_deposited
-            .checked_add(amount)
-            .ok_or(VaultError::AmountOverflow)?;
-        
-        vault.total_shares = vault.total_shares
-            .checked_add(amount)
-            .ok_or(VaultError::AmountOverflow)?;
+    pub token_program: Program<'info, Token>,
+}
 
-        let user_deposit = &mut ctx.accounts.user_deposit;
-        user_deposit.user = ctx.accounts.depositor.key();
-        user_deposit.vault = vault.key();
-        user_deposit.shares = user_deposit.shares
-            .checked_add(amount)
-            .ok_or(VaultError::AmountOverflow)?;
-        user_deposit.bump = ctx.bumps.user_deposit;
+#[derive(Accounts)]
+pub struct SetFees<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
 
-        msg!("Deposited {} lamports. User now has {} shares", amount, user_deposit.shares);
-        Ok(())
-    }
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
 
-    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
-        let user_deposit = &ctx.accounts.user_deposit;
-        let vault = &mut ctx.accounts.vault;
+    #[account(address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+}
 
-        require!(user_deposit.shares > 0, VaultError::NoDeposit);
+#[derive(Accounts)]
+pub struct ClaimFees<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
 
-        let vault_balance = vault.to_account_info().lamports();
-        
-      
-        let user_share = (user_deposit.shares as u128)
-            .checked_mul(vault_balance as u128)
-            .ok_or(VaultError::AmountOverflow)?
-            .checked_div(vault.total_shares as u128)
-            .ok_or(VaultError::DivisionByZero)?;
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
 
-        require!(user_share <= u64::MAX as u128, VaultError::AmountOverflow);
-        let user_share = user_share as u64;
+    #[account(address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
 
-        let owner_fee = user_share
-            .checked_mul(2)
-            .ok_or(VaultError::AmountOverflow)?
-            .checked_div(100)
-            .ok_or(VaultError::DivisionByZero)?;
+    #[account(mut, address = vault.share_mint)]
+    pub share_mint: Account<'info, Mint>,
 
-        let net_withdrawal = user_share
-            .checked_sub(owner_fee)
-            .ok_or(VaultError::InsufficientFunds)?;
+    #[account(
+        mut,
+        constraint = owner_shares.mint == share_mint.key(),
+        constraint = owner_shares.owner == owner.key()
+    )]
+    pub owner_shares: Account<'info, TokenAccount>,
 
-        require!(
-            vault_balance >= user_share,
-            VaultError::InsufficientFunds
-        );
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct OwnerWithdraw<'info> {
+    #[account(mut, constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
 
-        // Transfer net amount to user
-        **vault.to_account_info().try_borrow_mut_lamports()? -= net_withdrawal;
-        **ctx.accounts.depositor.to_account_info().try_borrow_mut_lamports()? += net_withdrawal;
+    #[account(mut, address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
 
-        // Transfer fee to owner
-        **vault.to_account_info().try_borrow_mut_lamports()? -= owner_fee;
-        **ctx.accounts.owner.to_account_info().try_borrow_mut_lamports()? += owner_fee;
+    #[account(
+        mut,
+        constraint = owner_assets.mint == vault.asset_mint,
+        constraint = owner_assets.owner == owner.key()
+    )]
+    pub owner_assets: Account<'info, TokenAccount>,
 
-        vault.total_shares = vault.total_shares
-            .checked_sub(user_deposit.shares)
-            .ok_or(VaultError::AmountOverflow)?;
-        
-        vault.total_deposited = vault.total_deposited
-            .checked_sub(user_deposit.shares)
-            .ok_or(VaultError::AmountOverflow)?;
+    pub token_program: Program<'info, Token>,
+}
 
-        msg!(
-            "Withdrawn {} lamports (fee: {} lamports). Profit: {} lamports",
-            net_withdrawal,
-            owner_fee,
-            user_share.saturating_sub(user_deposit.shares)
-        );
+#[derive(Accounts)]
+pub struct AnnounceTransfer<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
 
-        Ok(())
-    }
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
 
-    pub fn owner_withdraw(ctx: Context<OwnerWithdraw>, amount: u64) -> Result<()> {
-        let vault = &ctx.accounts.vault;
-        let vault_balance = vault.to_account_info().lamports();
+    #[account(constraint = destination.mint == vault.asset_mint)]
+    pub destination: Account<'info, TokenAccount>,
+}
 
-        require!(amount > 0, VaultError::InvalidAmount);
-        require!(vault_balance >= amount, VaultError::InsufficientFunds);
+#[derive(Accounts)]
+pub struct ExecuteTransfer<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
 
-        let seeds = &[b"vault".as_ref(), &[vault.bump]];
-        let signer = &[&seeds[..]];
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
 
-        let cpi_context = CpiContext::new_with_signer(
-            ctx.accounts.system_program.to_account_info(),
-            system_program::Transfer {
-                from: vault.to_account_info(),
-                to: ctx.accounts.owner.to_account_info(),
-            },
-            signer,
-        );
-        system_program::transfer(cpi_context, amount)?;
+    #[account(mut, address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
 
-        msg!("Owner withdrew {} lamports", amount);
-        Ok(())
-    }
+    #[account(mut, address = vault.pending_transfer_destination)]
+    pub destination: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct CancelTransfer<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
 
-#[account]
-pub struct Vault {
-    pub owner: Pubkey,           
-    pub total_deposited: u64,    
-    pub total_shares: u64,       
-    pub bump: u8,
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
 }
 
-#[account]
-pub struct UserDeposit {
-    pub user: Pubkey,    
-    pub vault: Pubkey,   
-    pub shares: u64,     
-    pub bump: u8,        
+#[derive(Accounts)]
+pub struct ManageStrategies<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
 }
 
+#[derive(Accounts)]
+pub struct Strategize<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut, address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+
+    #[account(address = vault.share_mint)]
+    pub share_mint: Account<'info, Mint>,
+
+    /// CHECK: Must match the whitelisted program of the selected strategy
+    #[account(executable)]
+    pub strategy_program: UncheckedAccount<'info>,
+}
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(mut)]
+pub struct ConfigureVault<'info> {
+    #[account(constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
     pub owner: Signer<'info>,
-    
+
     #[account(
-        init,
-        payer = owner,
-        space = 8 + 32 + 8 + 8 + 1,
-        seeds = [b"vault"],
-        bump
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
     )]
     pub vault: Account<'info, Vault>,
-    
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Deposit<'info> {
+pub struct SetPaused<'info> {
+    #[account(
+        constraint = authority.key() == vault.owner
+            || authority.key() == vault.guardian @ VaultError::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+}
+
+/// Read-only accounts for `preview_deposit`; the position identifies the
+/// depositor whose cap and allowlist entry are checked
+#[derive(Accounts)]
+pub struct PreviewDeposit<'info> {
+    #[account(
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+
+    #[account(has_one = vault)]
+    pub position: Account<'info, UserPosition>,
+}
+
+/// Read-only accounts for the preview instructions
+#[derive(Accounts)]
+pub struct VaultView<'info> {
+    #[account(
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct RequestWithdrawal<'info> {
     #[account(mut)]
     pub depositor: Signer<'info>,
-    
+
     #[account(
         mut,
-        seeds = [b"vault"],
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
         bump = vault.bump
     )]
     pub vault: Account<'info, Vault>,
-    
+
+    /// One request per depositor per epoch
     #[account(
-        mut,
-        seeds = [b"deposit", vault.key().as_ref(), depositor.key().as_ref()],
+        init,
+        payer = depositor,
+        space = 8 + 32 + 32 + 8 + 8 + 1,
+        seeds = [
+            b"withdrawal",
+            vault.key().as_ref(),
+            depositor.key().as_ref(),
+            &vault.current_epoch.to_le_bytes()
+        ],
         bump
     )]
-    pub user_deposit: Account<'info, UserDeposit>,
-    
+    pub request: Account<'info, WithdrawalRequest>,
+
+    #[account(mut, address = vault.share_mint)]
+    pub share_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = depositor_shares.mint == share_mint.key(),
+        constraint = depositor_shares.owner == depositor.key()
+    )]
+    pub depositor_shares: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"position", vault.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
+pub struct CloseEpoch<'info> {
     #[account(mut)]
-    pub depositor: Signer<'info>,
-    
+    pub payer: Signer<'info>,
+
     #[account(
         mut,
-        seeds = [b"vault"],
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
         bump = vault.bump
     )]
     pub vault: Account<'info, Vault>,
-    
+
+    #[account(address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+
     #[account(
-        mut,
-        seeds = [b"deposit", vault.key().as_ref(), depositor.key().as_ref()],
-        bump = user_deposit.bump,
-        close = depositor
+        init,
+        payer = payer,
+        space = 8 + 32 + 8 + 8 + 8 + 8 + 8 + 32 + 1,
+        seeds = [b"epoch", vault.key().as_ref(), &vault.current_epoch.to_le_bytes()],
+        bump
     )]
-    pub user_deposit: Account<'info, UserDeposit>,
-    
-    /// CHECK: Owner account to receive fees
-    #[account(mut, constraint = owner.key() == vault.owner)]
-    pub owner: AccountInfo<'info>,
-    
+    pub epoch_record: Account<'info, EpochRecord>,
+
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct OwnerWithdraw<'info> {
-    #[account(mut, constraint = owner.key() == vault.owner @ VaultError::Unauthorized)]
-    pub owner: Signer<'info>,
-    
+pub struct ClaimWithdrawal<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
     #[account(
         mut,
-        seeds = [b"vault"],
+        seeds = [b"vault", vault.asset_mint.as_ref(), vault.owner.as_ref()],
         bump = vault.bump
     )]
     pub vault: Account<'info, Vault>,
-    
-    pub system_program: Program<'info, System>,
+
+    #[account(
+        mut,
+        close = depositor,
+        has_one = vault,
+        constraint = request.owner == depositor.key() @ VaultError::Unauthorized
+    )]
+    pub request: Account<'info, WithdrawalRequest>,
+
+    #[account(
+        mut,
+        seeds = [b"epoch", vault.key().as_ref(), &request.epoch.to_le_bytes()],
+        bump = epoch_record.bump
+    )]
+    pub epoch_record: Account<'info, EpochRecord>,
+
+    /// Receives the epoch record's rent when the last claim closes it
+    #[account(mut, address = epoch_record.payer)]
+    pub epoch_payer: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"position", vault.key().as_ref(), depositor.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, UserPosition>,
+
+    #[account(mut, address = vault.asset_vault)]
+    pub asset_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = depositor_assets.mint == vault.asset_mint,
+        constraint = depositor_assets.owner == depositor.key()
+    )]
+    pub depositor_assets: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
 }
 
 #[error_code]
 pub enum VaultError {
     #[msg("Invalid amount to deposit or withdraw")]
     InvalidAmount,
-    
+
     #[msg("Amount overflow error")]
     AmountOverflow,
-    
-    #[msg("User has no deposit in the vault")]
+
+    #[msg("User holds no vault shares")]
     NoDeposit,
-    
+
     #[msg("Insufficient funds in vault")]
     InsufficientFunds,
-    
+
     #[msg("Division by zero")]
     DivisionByZero,
-    
+
     #[msg("Unauthorized: only owner can perform this action")]
     Unauthorized,
+
+    #[msg("First deposit must exceed the minimum locked shares")]
+    DepositTooSmall,
+
+    #[msg("Deposit too small to mint any shares")]
+    ZeroShares,
+
+    #[msg("Withdrawal exceeds the shares held")]
+    InsufficientShares,
+
+    #[msg("Withdrawal too small to return any assets")]
+    ZeroAssets,
+
+    #[msg("Fee exceeds the maximum allowed")]
+    FeeTooHigh,
+
+    #[msg("Amount exceeds the fees accrued to the owner")]
+    ExceedsAccruedFees,
+
+    #[msg("A transfer is already pending")]
+    TransferPending,
+
+    #[msg("No transfer has been announced")]
+    NoTransferPending,
+
+    #[msg("Transfer timelock has not elapsed")]
+    TimelockNotElapsed,
+
+    #[msg("Too many strategies")]
+    TooManyStrategies,
+
+    #[msg("Strategy already registered")]
+    StrategyExists,
+
+    #[msg("Invalid strategy")]
+    InvalidStrategy,
+
+    #[msg("Strategy still holds allocated funds")]
+    StrategyHasFunds,
+
+    #[msg("Strategy took more than the allocated amount")]
+    AllocationExceeded,
+
+    #[msg("Harvest reduced vault assets")]
+    HarvestLoss,
+
+    #[msg("Current epoch has not ended yet")]
+    EpochNotOver,
+
+    #[msg("Not enough liquid assets to pay this withdrawal yet")]
+    InsufficientLiquidity,
+
+    #[msg("Vault deposits are paused")]
+    VaultPaused,
+
+    #[msg("Depositor is not on the allowlist")]
+    NotAllowlisted,
+
+    #[msg("Deposit would exceed the vault's deposit cap")]
+    DepositCapExceeded,
+
+    #[msg("Deposit would exceed the per-user cap")]
+    UserCapExceeded,
+
+    #[msg("Program cannot be used as a strategy")]
+    StrategyNotAllowed,
+
+    #[msg("Strategy call moved assets out of the vault")]
+    VaultOutflow,
+
+    #[msg("Strategy call changed the share supply")]
+    ShareSupplyChanged,
+
+    #[msg("Strategy call changed an authority on the vault's token accounts")]
+    VaultAuthorityChanged,
+
+    #[msg("Position still holds shares or an unclaimed withdrawal")]
+    PositionNotEmpty,
 }
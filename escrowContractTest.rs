@@ -2,7 +2,9 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { EscrowContract } from "../target/types/escrow_contract";
 import { assert } from "chai";
-import { LAMPORTS_PER_SOL, SystemProgram } from "@solana/web3.js";
+import { createMint, getAccount, getOrCreateAssociatedTokenAccount, mintTo } from "@solana/spl-token";
+import { createHash } from "crypto";
+import { Ed25519Program, LAMPORTS_PER_SOL, SystemProgram, Transaction } from "@solana/web3.js";
 
 describe("escrow_contract", () => {
   const provider = anchor.AnchorProvider.local();
@@ -11,8 +13,30 @@ describe("escrow_contract", () => {
   const program = anchor.workspace.escrowContract as Program<EscrowContract>;
   const buyer = provider.wallet;
   const seller = anchor.web3.Keypair.generate();
+  const feeRecipient = anchor.web3.Keypair.generate();
+  const feeBps = 100; // 1%
   let escrowPda: anchor.web3.PublicKey;
   let bump: number;
+
+  const chainTime = async () =>
+    provider.connection.getBlockTime(await provider.connection.getSlot());
+
+  // Create, accept and fully fund a fresh escrow between buyer and seller
+  const fundEscrow = async (amount: number, payees = []) => {
+    await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), [])
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(amount), Array(32).fill(0), payees)
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(amount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+  };
   
   before(async () => {
     // Airdrop SOL to seller for testing
@@ -25,10 +49,54 @@ describe("escrow_contract", () => {
     );
   });
 
+  it("initialize escrow config", async () => {
+    // Only the upgrade authority (the deploying wallet) may create the config
+    const [programData] = anchor.web3.PublicKey.findProgramAddressSync(
+      [program.programId.toBuffer()],
+      new anchor.web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+
+    const impostor = anchor.web3.Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(impostor.publicKey, LAMPORTS_PER_SOL)
+    );
+    try {
+      await program.methods
+        .initConfig(feeBps, impostor.publicKey)
+        .accounts({
+          admin: impostor.publicKey,
+          program: program.programId,
+          programData,
+        })
+        .signers([impostor])
+        .rpc();
+      assert.fail("Only the upgrade authority should initialize the config");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    await program.methods
+      .initConfig(feeBps, feeRecipient.publicKey)
+      .accounts({
+        admin: buyer.publicKey,
+        program: program.programId,
+        programData,
+      })
+      .rpc();
+
+    const [configPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow_config")],
+      program.programId
+    );
+    const config = await program.account.escrowConfig.fetch(configPda);
+    assert.equal(config.feeBps, feeBps, "Fee bps mismatch");
+    assert.ok(config.feeRecipient.equals(feeRecipient.publicKey), "Fee recipient mismatch");
+  });
+
   it("initialize escrow", async () => {
     // Initialize the escrow contract with the buyer, seller, and the escrow PDA
     await program.methods
-      .initEscrow()
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), [])
       .accounts({
         buyer: buyer.publicKey,
         seller: seller.publicKey,
@@ -42,6 +110,21 @@ describe("escrow_contract", () => {
     assert.equal(escrow.amount.toNumber(), 0, "Escrow amount should be 0 after initialization");
   });
 
+  it("seller accepts escrow", async () => {
+    await program.methods
+      .acceptEscrow(new anchor.BN(1 * LAMPORTS_PER_SOL), Array(32).fill(0), [])
+      .accounts({
+        seller: seller.publicKey,
+        buyer: buyer.publicKey,
+      })
+      .signers([seller])
+      .rpc();
+
+    const escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.equal(escrow.agreedAmount.toNumber(), 1 * LAMPORTS_PER_SOL, "Agreed amount mismatch");
+    assert.ok("accepted" in escrow.state, "Escrow should be accepted");
+  });
+
   it("depositing 1 sol in escrow", async () => {
     const depositAmount = 1 * LAMPORTS_PER_SOL;
 
@@ -73,17 +156,1159 @@ describe("escrow_contract", () => {
 
     // Perform the release transaction
     await program.methods
-      .release()
+      .release(false)
       .accounts({
         buyer: buyer.publicKey,
         seller: seller.publicKey,
+        feeRecipient: feeRecipient.publicKey,
       })
       .rpc();
 
     // Get the seller's balance after the release
     const balanceAfterRelease = await provider.connection.getBalance(seller.publicKey);
 
-    // Check that the seller's balance has increased by the correct amount (1 SOL)
-    assert.equal(balanceAfterRelease, balanceBeforeRelease + 1 * LAMPORTS_PER_SOL, "Seller balance mismatch after release");
+    // Check that the seller received 1 SOL minus the platform fee
+    const fee = (1 * LAMPORTS_PER_SOL * feeBps) / 10_000;
+    assert.equal(balanceAfterRelease, balanceBeforeRelease + 1 * LAMPORTS_PER_SOL - fee, "Seller balance mismatch after release");
+    assert.equal(await provider.connection.getBalance(feeRecipient.publicKey), fee, "Fee recipient balance mismatch");
+  });
+
+  it("close completed escrow", async () => {
+    const buyerBalanceBefore = await provider.connection.getBalance(buyer.publicKey);
+
+    await program.methods
+      .closeEscrow()
+      .accounts({
+        buyer: buyer.publicKey,
+      })
+      .rpc();
+
+    const escrowInfo = await provider.connection.getAccountInfo(escrowPda);
+    assert.isNull(escrowInfo, "Escrow account should be closed");
+
+    // Rent refund outweighs the transaction fee
+    const buyerBalanceAfter = await provider.connection.getBalance(buyer.publicKey);
+    assert.isAbove(buyerBalanceAfter, buyerBalanceBefore, "Buyer should receive rent back");
+  });
+
+  it("release requires a signed oracle attestation", async () => {
+    const oracle = anchor.web3.Keypair.generate();
+    const condition = Buffer.alloc(32, 7);
+    const depositAmount = 1 * LAMPORTS_PER_SOL;
+
+    await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), [])
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .setOracleCondition(oracle.publicKey, [...condition])
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    // Without an attestation the release is rejected
+    try {
+      await program.methods
+        .release(false)
+        .accounts({ buyer: buyer.publicKey, seller: seller.publicKey, feeRecipient: feeRecipient.publicKey })
+        .rpc();
+      assert.fail("Release should require an oracle attestation");
+    } catch (err) {
+      assert.include(err.toString(), "MissingOracleAttestation");
+    }
+
+    // Oracle signs `escrow || created_at || condition || outcome` (1 = release)
+    const { createdAt } = await program.account.escrowAccount.fetch(escrowPda);
+    const message = Buffer.concat([
+      escrowPda.toBuffer(),
+      createdAt.toArrayLike(Buffer, "le", 8),
+      condition,
+      Buffer.from([1]),
+    ]);
+    const attestation = Ed25519Program.createInstructionWithPrivateKey({
+      privateKey: oracle.secretKey,
+      message,
+    });
+
+    const sellerBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    await program.methods
+      .release(true)
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey, feeRecipient: feeRecipient.publicKey })
+      .preInstructions([attestation])
+      .rpc();
+
+    const fee = (depositAmount * feeBps) / 10_000;
+    const sellerBalanceAfter = await provider.connection.getBalance(seller.publicKey);
+    assert.equal(sellerBalanceAfter, sellerBalanceBefore + depositAmount - fee, "Seller balance mismatch after oracle release");
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Escrow should be closed on release");
+  });
+
+  it("partial refunds tolerate lamports donated to the escrow", async () => {
+    const depositAmount = 1 * LAMPORTS_PER_SOL;
+    const donation = 5_000;
+
+    await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), [])
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    // Anyone can send lamports straight to the PDA
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        SystemProgram.transfer({
+          fromPubkey: seller.publicKey,
+          toPubkey: escrowPda,
+          lamports: donation,
+        })
+      ),
+      [seller]
+    );
+
+    const partial = depositAmount / 4;
+    await program.methods
+      .partialRefund(new anchor.BN(partial))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    let escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.equal(escrow.amount.toNumber(), depositAmount - partial, "Escrow amount mismatch after partial refund");
+    assert.ok("buyerDeposit" in escrow.state, "Escrow should still hold a deposit");
+
+    // The surplus is never paid out as escrow funds; it returns with the rent on close
+    const escrowBalanceBefore = await provider.connection.getBalance(escrowPda);
+    const buyerBalanceBefore = await provider.connection.getBalance(buyer.publicKey);
+    await program.methods
+      .refund(true)
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Escrow should be closed on refund");
+    const buyerBalanceAfter = await provider.connection.getBalance(buyer.publicKey);
+    assert.isAbove(
+      buyerBalanceAfter,
+      buyerBalanceBefore + escrowBalanceBefore - 10_000,
+      "Buyer should receive the remaining deposit, rent and donation"
+    );
+  });
+
+  it("swaps tokens atomically and closes the swap", async () => {
+    const maker = buyer;
+    const taker = seller;
+    const mintA = await createMint(provider.connection, maker.payer, maker.publicKey, null, 0);
+    const mintB = await createMint(provider.connection, maker.payer, maker.publicKey, null, 0);
+    const tokenAccount = async (mint: anchor.web3.PublicKey, owner: anchor.web3.PublicKey) =>
+      (await getOrCreateAssociatedTokenAccount(provider.connection, maker.payer, mint, owner)).address;
+    const balance = async (account: anchor.web3.PublicKey) =>
+      Number((await getAccount(provider.connection, account)).amount);
+
+    const makerTokenA = await tokenAccount(mintA, maker.publicKey);
+    const makerTokenB = await tokenAccount(mintB, maker.publicKey);
+    const takerTokenA = await tokenAccount(mintA, taker.publicKey);
+    const takerTokenB = await tokenAccount(mintB, taker.publicKey);
+    await mintTo(provider.connection, maker.payer, mintA, makerTokenA, maker.payer, 200);
+    await mintTo(provider.connection, maker.payer, mintB, takerTokenB, maker.payer, 100);
+
+    const openSwap = async (nonce: number) => {
+      const [swapPda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("swap"), maker.publicKey.toBuffer(), new anchor.BN(nonce).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const [vaultA] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("swap_vault"), swapPda.toBuffer(), mintA.toBuffer()],
+        program.programId
+      );
+      const [vaultB] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("swap_vault"), swapPda.toBuffer(), mintB.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .initSwap(new anchor.BN(nonce), new anchor.BN(100), new anchor.BN(50))
+        .accounts({ maker: maker.publicKey, taker: taker.publicKey, mintA, mintB })
+        .rpc();
+      await program.methods
+        .depositSwap()
+        .accounts({ depositor: maker.publicKey, swapPda, depositorToken: makerTokenA, vault: vaultA })
+        .rpc();
+      return { swapPda, vaultA, vaultB };
+    };
+
+    // Nonces let the same maker run several swaps at once
+    const first = await openSwap(1);
+    const second = await openSwap(2);
+
+    await program.methods
+      .depositSwap()
+      .accounts({ depositor: taker.publicKey, swapPda: first.swapPda, depositorToken: takerTokenB, vault: first.vaultB })
+      .signers([taker])
+      .rpc();
+    await program.methods
+      .exchange()
+      .accounts({
+        caller: taker.publicKey,
+        maker: maker.publicKey,
+        swapPda: first.swapPda,
+        vaultA: first.vaultA,
+        vaultB: first.vaultB,
+        takerTokenA,
+        makerTokenB,
+      })
+      .signers([taker])
+      .rpc();
+
+    assert.equal(await balance(takerTokenA), 100, "Taker should receive token A");
+    assert.equal(await balance(makerTokenB), 50, "Maker should receive token B");
+    assert.isNull(await provider.connection.getAccountInfo(first.swapPda), "Swap should be closed");
+    assert.isNull(await provider.connection.getAccountInfo(first.vaultA), "Vault A should be closed");
+
+    // Cancelling before both sides fund returns the maker's deposit
+    await program.methods
+      .cancelSwap()
+      .accounts({
+        caller: taker.publicKey,
+        maker: maker.publicKey,
+        swapPda: second.swapPda,
+        vaultA: second.vaultA,
+        vaultB: second.vaultB,
+        makerTokenA,
+        takerTokenB,
+      })
+      .signers([taker])
+      .rpc();
+
+    assert.equal(await balance(makerTokenA), 100, "Maker should get the unswapped tokens back");
+    assert.isNull(await provider.connection.getAccountInfo(second.swapPda), "Cancelled swap should be closed");
+  });
+
+  it("HTLC pays the seller who reveals the preimage", async () => {
+    const depositAmount = 1 * LAMPORTS_PER_SOL;
+    const preimage = anchor.web3.Keypair.generate().publicKey.toBuffer();
+    const hashlock = [...createHash("sha256").update(preimage).digest()];
+
+    await program.methods
+      .initHtlc(hashlock, new anchor.BN((await chainTime()) + 600))
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    try {
+      await program.methods
+        .claimWithSecret(Array(32).fill(1))
+        .accounts({ seller: seller.publicKey, buyer: buyer.publicKey, feeRecipient: feeRecipient.publicKey })
+        .signers([seller])
+        .rpc();
+      assert.fail("A wrong preimage should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidPreimage");
+    }
+
+    const sellerBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    await program.methods
+      .claimWithSecret([...preimage])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey, feeRecipient: feeRecipient.publicKey })
+      .signers([seller])
+      .rpc();
+
+    const fee = (depositAmount * feeBps) / 10_000;
+    const sellerBalanceAfter = await provider.connection.getBalance(seller.publicKey);
+    assert.equal(sellerBalanceAfter, sellerBalanceBefore + depositAmount - fee, "Seller balance mismatch after claim");
+
+    await program.methods.closeEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+  });
+
+  it("HTLC refunds the buyer only after the timelock", async () => {
+    const depositAmount = LAMPORTS_PER_SOL / 2;
+    const preimage = anchor.web3.Keypair.generate().publicKey.toBuffer();
+    const hashlock = [...createHash("sha256").update(preimage).digest()];
+    const timelock = (await chainTime()) + 5;
+
+    await program.methods
+      .initHtlc(hashlock, new anchor.BN(timelock))
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    try {
+      await program.methods.refund(false).accounts({ buyer: buyer.publicKey }).rpc();
+      assert.fail("Refund should wait for the timelock");
+    } catch (err) {
+      assert.include(err.toString(), "TimelockActive");
+    }
+
+    while ((await chainTime()) <= timelock) {
+      await new Promise((resolve) => setTimeout(resolve, 1_000));
+    }
+
+    // Once expired the preimage no longer pays the seller
+    try {
+      await program.methods
+        .claimWithSecret([...preimage])
+        .accounts({ seller: seller.publicKey, buyer: buyer.publicKey, feeRecipient: feeRecipient.publicKey })
+        .signers([seller])
+        .rpc();
+      assert.fail("Claim should fail after the timelock");
+    } catch (err) {
+      assert.include(err.toString(), "TimelockExpired");
+    }
+
+    await program.methods.refund(true).accounts({ buyer: buyer.publicKey }).rpc();
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Escrow should be closed on refund");
+  });
+
+  it("streams vested SOL and closes finished streams", async () => {
+    const amount = LAMPORTS_PER_SOL / 2;
+    const now = await chainTime();
+    const streamPda = (nonce: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("stream"), buyer.publicKey.toBuffer(), new anchor.BN(nonce).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      )[0];
+
+    // Fully vested stream: the recipient withdraws everything and the account closes
+    await program.methods
+      .createStream(new anchor.BN(1), new anchor.BN(amount), new anchor.BN(now - 100), new anchor.BN(0), new anchor.BN(now - 1))
+      .accounts({ payer: buyer.publicKey, recipient: seller.publicKey })
+      .rpc();
+
+    // Still running stream with a far cliff, so nothing has vested yet
+    await program.methods
+      .createStream(new anchor.BN(2), new anchor.BN(amount), new anchor.BN(now), new anchor.BN(now + 5_000), new anchor.BN(now + 10_000))
+      .accounts({ payer: buyer.publicKey, recipient: seller.publicKey })
+      .rpc();
+
+    const recipientBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    await program.methods
+      .withdrawVested()
+      .accounts({ recipient: seller.publicKey, payer: buyer.publicKey, streamPda: streamPda(1) })
+      .signers([seller])
+      .rpc();
+    const recipientBalanceAfter = await provider.connection.getBalance(seller.publicKey);
+    assert.equal(recipientBalanceAfter, recipientBalanceBefore + amount, "Recipient should receive the full stream");
+    assert.isNull(await provider.connection.getAccountInfo(streamPda(1)), "Finished stream should be closed");
+
+    try {
+      await program.methods
+        .withdrawVested()
+        .accounts({ recipient: seller.publicKey, payer: buyer.publicKey, streamPda: streamPda(2) })
+        .signers([seller])
+        .rpc();
+      assert.fail("Nothing should be withdrawable before the cliff");
+    } catch (err) {
+      assert.include(err.toString(), "NothingVested");
+    }
+
+    // Cancelling before the cliff returns everything to the payer
+    const stream = await program.account.streamEscrow.fetch(streamPda(2));
+    assert.equal(stream.amount.toNumber(), amount, "Stream amount mismatch");
+    await program.methods
+      .cancelStream()
+      .accounts({ payer: buyer.publicKey, recipient: seller.publicKey, streamPda: streamPda(2) })
+      .rpc();
+    assert.equal(await provider.connection.getBalance(seller.publicKey), recipientBalanceAfter, "Recipient should receive nothing unvested");
+    assert.isNull(await provider.connection.getAccountInfo(streamPda(2)), "Cancelled stream should be closed");
+  });
+
+  it("amendments need the counterparty and honour the new seller payout", async () => {
+    const depositAmount = 1 * LAMPORTS_PER_SOL;
+    const newAmount = (6 * LAMPORTS_PER_SOL) / 10;
+    const sellerPayout = LAMPORTS_PER_SOL / 2;
+    await fundEscrow(depositAmount);
+
+    const { createdAt } = await program.account.escrowAccount.fetch(escrowPda);
+    const [amendment] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("amendment"), escrowPda.toBuffer(), createdAt.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    await program.methods
+      .proposeAmendment(new anchor.BN(newAmount), new anchor.BN(0), new anchor.BN(sellerPayout))
+      .accounts({ proposer: buyer.publicKey, escrowPda, amendment })
+      .rpc();
+
+    const acceptAccounts = {
+      buyer: buyer.publicKey,
+      proposer: buyer.publicKey,
+      escrowPda,
+      amendment,
+    };
+    try {
+      await program.methods
+        .acceptAmendment()
+        .accounts({ approver: buyer.publicKey, ...acceptAccounts })
+        .rpc();
+      assert.fail("The proposer should not accept their own amendment");
+    } catch (err) {
+      assert.include(err.toString(), "Unauthorized");
+    }
+
+    const escrowBalanceBefore = await provider.connection.getBalance(escrowPda);
+    await program.methods
+      .acceptAmendment()
+      .accounts({ approver: seller.publicKey, ...acceptAccounts })
+      .signers([seller])
+      .rpc();
+
+    // The deposit above the new amount goes back to the buyer
+    const escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.equal(escrow.amount.toNumber(), newAmount, "Escrow amount mismatch after amendment");
+    assert.equal(escrow.agreedAmount.toNumber(), newAmount, "Agreed amount mismatch after amendment");
+    assert.equal(escrow.sellerPayout.toNumber(), sellerPayout, "Seller payout mismatch after amendment");
+    assert.equal(
+      await provider.connection.getBalance(escrowPda),
+      escrowBalanceBefore - (depositAmount - newAmount),
+      "Excess deposit should leave the escrow"
+    );
+    assert.isNull(await provider.connection.getAccountInfo(amendment), "Amendment should be closed");
+
+    const sellerBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    await program.methods
+      .release(true)
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey, feeRecipient: feeRecipient.publicKey })
+      .rpc();
+
+    const fee = (sellerPayout * feeBps) / 10_000;
+    const sellerBalanceAfter = await provider.connection.getBalance(seller.publicKey);
+    assert.equal(sellerBalanceAfter, sellerBalanceBefore + sellerPayout - fee, "Seller should only receive the amended payout");
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Escrow should be closed on release");
+  });
+
+  it("splits the release across payees", async () => {
+    const depositAmount = 1 * LAMPORTS_PER_SOL;
+    const payeeA = anchor.web3.Keypair.generate().publicKey;
+    const payeeB = anchor.web3.Keypair.generate().publicKey;
+    await fundEscrow(depositAmount, [
+      { recipient: payeeA, shareBps: 7_000 },
+      { recipient: payeeB, shareBps: 3_000 },
+    ]);
+
+    // Payees must be passed in order as remaining accounts
+    try {
+      await program.methods
+        .release(false)
+        .accounts({ buyer: buyer.publicKey, seller: seller.publicKey, feeRecipient: feeRecipient.publicKey })
+        .remainingAccounts([
+          { pubkey: payeeB, isSigner: false, isWritable: true },
+          { pubkey: payeeA, isSigner: false, isWritable: true },
+        ])
+        .rpc();
+      assert.fail("Payees out of order should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "PayeeMismatch");
+    }
+
+    const sellerBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    await program.methods
+      .release(true)
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey, feeRecipient: feeRecipient.publicKey })
+      .remainingAccounts([
+        { pubkey: payeeA, isSigner: false, isWritable: true },
+        { pubkey: payeeB, isSigner: false, isWritable: true },
+      ])
+      .rpc();
+
+    // The last payee absorbs rounding dust
+    const payout = depositAmount - (depositAmount * feeBps) / 10_000;
+    const shareA = Math.floor((payout * 7_000) / 10_000);
+    assert.equal(await provider.connection.getBalance(payeeA), shareA, "Payee A share mismatch");
+    assert.equal(await provider.connection.getBalance(payeeB), payout - shareA, "Payee B share mismatch");
+    assert.equal(await provider.connection.getBalance(seller.publicKey), sellerBalanceBefore, "Seller should be paid through the payees");
+  });
+
+  it("settles NFT listings and closes them", async () => {
+    const price = LAMPORTS_PER_SOL / 4;
+    const balance = async (account: anchor.web3.PublicKey) =>
+      Number((await getAccount(provider.connection, account)).amount);
+
+    const listNft = async (nonce: number) => {
+      const nftMint = await createMint(provider.connection, buyer.payer, buyer.publicKey, null, 0);
+      const sellerNftAccount = (
+        await getOrCreateAssociatedTokenAccount(provider.connection, buyer.payer, nftMint, seller.publicKey)
+      ).address;
+      const buyerNftAccount = (
+        await getOrCreateAssociatedTokenAccount(provider.connection, buyer.payer, nftMint, buyer.publicKey)
+      ).address;
+      await mintTo(provider.connection, buyer.payer, nftMint, sellerNftAccount, buyer.payer, 1);
+
+      const [nftEscrow] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("nft_escrow"), seller.publicKey.toBuffer(), new anchor.BN(nonce).toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const [nftVault] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("nft_vault"), nftEscrow.toBuffer()],
+        program.programId
+      );
+
+      await program.methods
+        .listNft(new anchor.BN(nonce), new anchor.BN(price))
+        .accounts({ seller: seller.publicKey, buyer: buyer.publicKey, nftMint, sellerNftAccount })
+        .signers([seller])
+        .rpc();
+      return { nftEscrow, nftVault, sellerNftAccount, buyerNftAccount };
+    };
+
+    // Nonces let the same seller list several NFTs at once
+    const sold = await listNft(1);
+    const unsold = await listNft(2);
+
+    await program.methods
+      .depositPayment()
+      .accounts({ buyer: buyer.publicKey, nftEscrow: sold.nftEscrow })
+      .rpc();
+
+    const escrowBalance = await provider.connection.getBalance(sold.nftEscrow);
+    const vaultBalance = await provider.connection.getBalance(sold.nftVault);
+    const sellerBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    await program.methods
+      .settle()
+      .accounts({
+        caller: buyer.publicKey,
+        seller: seller.publicKey,
+        nftEscrow: sold.nftEscrow,
+        nftVault: sold.nftVault,
+        buyerNftAccount: sold.buyerNftAccount,
+      })
+      .rpc();
+
+    assert.equal(await balance(sold.buyerNftAccount), 1, "Buyer should receive the NFT");
+    assert.isNull(await provider.connection.getAccountInfo(sold.nftEscrow), "Listing should be closed");
+    assert.isNull(await provider.connection.getAccountInfo(sold.nftVault), "NFT vault should be closed");
+
+    // Seller receives the price plus the listing and vault rent
+    const sellerBalanceAfter = await provider.connection.getBalance(seller.publicKey);
+    assert.equal(sellerBalanceAfter, sellerBalanceBefore + escrowBalance + vaultBalance, "Seller balance mismatch after settle");
+
+    await program.methods
+      .refundNft()
+      .accounts({
+        caller: seller.publicKey,
+        seller: seller.publicKey,
+        buyer: buyer.publicKey,
+        nftEscrow: unsold.nftEscrow,
+        nftVault: unsold.nftVault,
+        sellerNftAccount: unsold.sellerNftAccount,
+      })
+      .signers([seller])
+      .rpc();
+
+    assert.equal(await balance(unsold.sellerNftAccount), 1, "Seller should get the unsold NFT back");
+    assert.isNull(await provider.connection.getAccountInfo(unsold.nftEscrow), "Refunded listing should be closed");
+  });
+
+  it("records evidence per dispute and waits for the evidence window", async () => {
+    // A separate buyer, since the dispute stays open for the evidence window
+    const disputeBuyer = anchor.web3.Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(disputeBuyer.publicKey, 2 * LAMPORTS_PER_SOL)
+    );
+    const [disputeEscrow] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), disputeBuyer.publicKey.toBuffer()],
+      program.programId
+    );
+    const depositAmount = LAMPORTS_PER_SOL / 2;
+
+    await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), [])
+      .accounts({ buyer: disputeBuyer.publicKey, seller: seller.publicKey })
+      .signers([disputeBuyer])
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: disputeBuyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: disputeBuyer.publicKey })
+      .signers([disputeBuyer])
+      .rpc();
+
+    await program.methods
+      .raiseDispute()
+      .accounts({ party: seller.publicKey, escrowPda: disputeEscrow })
+      .signers([seller])
+      .rpc();
+    const { disputedAt } = await program.account.escrowAccount.fetch(disputeEscrow);
+
+    const evidencePda = (party: anchor.web3.PublicKey, index: number) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("evidence"),
+          disputeEscrow.toBuffer(),
+          party.toBuffer(),
+          disputedAt.toArrayLike(Buffer, "le", 8),
+          Buffer.from([index]),
+        ],
+        program.programId
+      )[0];
+
+    const contentHash = Array(32).fill(9);
+    const evidence = evidencePda(disputeBuyer.publicKey, 0);
+    await program.methods
+      .submitEvidence(0, contentHash, "ipfs://evidence-0")
+      .accounts({ party: disputeBuyer.publicKey, escrowPda: disputeEscrow, evidence })
+      .signers([disputeBuyer])
+      .rpc();
+
+    const record = await program.account.evidence.fetch(evidence);
+    assert.ok(record.party.equals(disputeBuyer.publicKey), "Evidence party mismatch");
+    assert.equal(record.uri, "ipfs://evidence-0", "Evidence URI mismatch");
+
+    // Each party submits under consecutive indexes
+    try {
+      await program.methods
+        .submitEvidence(2, contentHash, "ipfs://evidence-2")
+        .accounts({ party: disputeBuyer.publicKey, escrowPda: disputeEscrow, evidence: evidencePda(disputeBuyer.publicKey, 2) })
+        .signers([disputeBuyer])
+        .rpc();
+      assert.fail("Skipping an evidence index should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidEvidenceIndex");
+    }
+
+    try {
+      await program.methods
+        .resolveDispute(true)
+        .accounts({
+          arbiter: buyer.publicKey,
+          buyer: disputeBuyer.publicKey,
+          seller: seller.publicKey,
+          escrowPda: disputeEscrow,
+          feeRecipient: feeRecipient.publicKey,
+        })
+        .rpc();
+      assert.fail("Disputes should not resolve during the evidence window");
+    } catch (err) {
+      assert.include(err.toString(), "EvidenceWindowOpen");
+    }
+  });
+
+  it("emits events and keeps a deposit log", async () => {
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const events = async (signature: string) => {
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      return [...parser.parseLogs(tx.meta.logMessages)];
+    };
+    const names = async (signature: string) => (await events(signature)).map((e) => e.name.toLowerCase());
+
+    let signature = await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), [])
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc({ commitment: "confirmed" });
+    assert.include(await names(signature), "escrowcreated", "Init should emit EscrowCreated");
+
+    const agreed = LAMPORTS_PER_SOL / 2;
+    await program.methods
+      .acceptEscrow(new anchor.BN(agreed), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+
+    // Top up in two deposits, each logged with its amount
+    const deposits = [(3 * LAMPORTS_PER_SOL) / 10, (2 * LAMPORTS_PER_SOL) / 10];
+    for (const amount of deposits) {
+      signature = await program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts({ buyer: buyer.publicKey })
+        .rpc({ commitment: "confirmed" });
+      assert.include(await names(signature), "deposited", "Deposit should emit Deposited");
+    }
+
+    const escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.deepEqual(
+      escrow.deposits.map((d) => d.amount.toNumber()),
+      deposits,
+      "Deposit log mismatch"
+    );
+    assert.isAtLeast(escrow.updatedAt.toNumber(), escrow.createdAt.toNumber(), "Timestamps out of order");
+
+    const refundAmount = LAMPORTS_PER_SOL / 10;
+    signature = await program.methods
+      .partialRefund(new anchor.BN(refundAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc({ commitment: "confirmed" });
+    assert.include(await names(signature), "refunded", "Partial refund should emit Refunded");
+
+    signature = await program.methods
+      .release(true)
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey, feeRecipient: feeRecipient.publicKey })
+      .rpc({ commitment: "confirmed" });
+    const released = (await events(signature)).find((e) => e.name.toLowerCase() === "released");
+    assert.ok(released, "Release should emit Released");
+    assert.equal(released.data.amount.toNumber(), agreed - refundAmount, "Released amount mismatch");
+    assert.equal(
+      released.data.fee.toNumber(),
+      ((agreed - refundAmount) * feeBps) / 10_000,
+      "Released fee mismatch"
+    );
+  });
+
+  it("deadline claims need a deadline that has passed", async () => {
+    const depositAmount = LAMPORTS_PER_SOL / 4;
+    const crank = anchor.web3.Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(crank.publicKey, LAMPORTS_PER_SOL)
+    );
+    const claimAccounts = {
+      caller: crank.publicKey,
+      buyer: buyer.publicKey,
+      seller: seller.publicKey,
+      feeRecipient: feeRecipient.publicKey,
+    };
+    const openEscrow = async (releaseAfter: number, refundAfter: number) => {
+      await program.methods
+        .initEscrow(new anchor.BN(releaseAfter), new anchor.BN(refundAfter), [])
+        .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+        .rpc();
+      await program.methods
+        .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+        .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+        .signers([seller])
+        .rpc();
+      await program.methods
+        .deposit(new anchor.BN(depositAmount))
+        .accounts({ buyer: buyer.publicKey })
+        .rpc();
+    };
+
+    // Release deadline far in the future, no refund deadline at all
+    await openEscrow((await chainTime()) + 10_000, 0);
+    try {
+      await program.methods.claimAfterDeadline().accounts(claimAccounts).signers([crank]).rpc();
+      assert.fail("Claim should wait for the release deadline");
+    } catch (err) {
+      assert.include(err.toString(), "DeadlineNotReached");
+    }
+    try {
+      await program.methods
+        .reclaimAfterDeadline()
+        .accounts({ caller: crank.publicKey, buyer: buyer.publicKey })
+        .signers([crank])
+        .rpc();
+      assert.fail("Reclaim should need a refund deadline");
+    } catch (err) {
+      assert.include(err.toString(), "DeadlineNotSet");
+    }
+    await program.methods.refund(true).accounts({ buyer: buyer.publicKey }).rpc();
+
+    // Refund deadline only, so the seller side is not claimable
+    await openEscrow(0, (await chainTime()) + 10_000);
+    try {
+      await program.methods.claimAfterDeadline().accounts(claimAccounts).signers([crank]).rpc();
+      assert.fail("Claim should need a release deadline");
+    } catch (err) {
+      assert.include(err.toString(), "DeadlineNotSet");
+    }
+    try {
+      await program.methods
+        .reclaimAfterDeadline()
+        .accounts({ caller: crank.publicKey, buyer: buyer.publicKey })
+        .signers([crank])
+        .rpc();
+      assert.fail("Reclaim should wait for the refund deadline");
+    } catch (err) {
+      assert.include(err.toString(), "DeadlineNotReached");
+    }
+    await program.methods.refund(true).accounts({ buyer: buyer.publicKey }).rpc();
+  });
+
+  it("anyone can crank an expired deadline to the stored parties", async () => {
+    const depositAmount = LAMPORTS_PER_SOL / 4;
+    const crank = anchor.web3.Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(crank.publicKey, LAMPORTS_PER_SOL)
+    );
+
+    // Deadlines of 1 are long past
+    await program.methods
+      .initEscrow(new anchor.BN(1), new anchor.BN(0), [])
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    const sellerBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    const feeBalanceBefore = await provider.connection.getBalance(feeRecipient.publicKey);
+    await program.methods
+      .claimAfterDeadline()
+      .accounts({
+        caller: crank.publicKey,
+        buyer: buyer.publicKey,
+        seller: seller.publicKey,
+        feeRecipient: feeRecipient.publicKey,
+      })
+      .signers([crank])
+      .rpc();
+
+    // The platform fee applies on the claim path too
+    const fee = (depositAmount * feeBps) / 10_000;
+    assert.equal(
+      await provider.connection.getBalance(seller.publicKey),
+      sellerBalanceBefore + depositAmount - fee,
+      "Seller should receive the deposit minus the fee"
+    );
+    assert.equal(
+      await provider.connection.getBalance(feeRecipient.publicKey),
+      feeBalanceBefore + fee,
+      "Fee recipient should receive the fee"
+    );
+    let escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.ok("completed" in escrow.state, "Claimed escrow should be completed");
+    await program.methods.closeEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+
+    await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(1), [])
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    // The crank pays the transaction fee, so the buyer's gain is exact
+    const buyerBalanceBefore = await provider.connection.getBalance(buyer.publicKey);
+    const tx = await program.methods
+      .reclaimAfterDeadline()
+      .accounts({ caller: crank.publicKey, buyer: buyer.publicKey })
+      .transaction();
+    tx.feePayer = crank.publicKey;
+    await anchor.web3.sendAndConfirmTransaction(provider.connection, tx, [crank]);
+
+    assert.equal(
+      await provider.connection.getBalance(buyer.publicKey),
+      buyerBalanceBefore + depositAmount,
+      "Buyer should receive the full deposit"
+    );
+    escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.ok("cancelled" in escrow.state, "Reclaimed escrow should be cancelled");
+    await program.methods.closeEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+  });
+
+  it("caps milestone deposits on the running total and sweeps leftovers", async () => {
+    const milestoneAmount = LAMPORTS_PER_SOL / 10;
+    const milestones = [2, 2, 1].map((units, i) => ({
+      amount: new anchor.BN(units * milestoneAmount),
+      descriptionHash: Array(32).fill(i),
+    }));
+    const agreed = 5 * milestoneAmount;
+    const releaseAccounts = {
+      buyer: buyer.publicKey,
+      seller: seller.publicKey,
+      feeRecipient: feeRecipient.publicKey,
+    };
+
+    await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), milestones)
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(agreed), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(agreed))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+    await program.methods.releaseMilestone(0).accounts(releaseAccounts).rpc();
+
+    // A paid-out milestone does not free room for another deposit
+    try {
+      await program.methods
+        .deposit(new anchor.BN(milestoneAmount))
+        .accounts({ buyer: buyer.publicKey })
+        .rpc();
+      assert.fail("Deposits should be capped on the running total");
+    } catch (err) {
+      assert.include(err.toString(), "ExceedsAgreedAmount");
+    }
+
+    // A refunded milestone does, and the extra deposit is swept back at the end
+    await program.methods.refundMilestone(1).accounts({ buyer: buyer.publicKey }).rpc();
+    await program.methods
+      .deposit(new anchor.BN(2 * milestoneAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+    let escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.equal(escrow.netDeposited.toNumber(), agreed, "Net deposits mismatch");
+
+    const escrowBalanceBefore = await provider.connection.getBalance(escrowPda);
+    await program.methods.releaseMilestone(2).accounts(releaseAccounts).rpc();
+
+    escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.ok("completed" in escrow.state, "Escrow should complete on the last milestone");
+    assert.equal(escrow.amount.toNumber(), 0, "Leftover should be swept out of the escrow");
+    assert.equal(
+      await provider.connection.getBalance(escrowPda),
+      escrowBalanceBefore - 3 * milestoneAmount,
+      "Escrow should pay the last milestone and the leftover"
+    );
+
+    await program.methods.closeEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Completed escrow should close");
+  });
+
+  it("settles milestones one index at a time", async () => {
+    const milestones = [3, 2].map((tenths, i) => ({
+      amount: new anchor.BN((tenths * LAMPORTS_PER_SOL) / 10),
+      descriptionHash: Array(32).fill(i),
+    }));
+    const total = LAMPORTS_PER_SOL / 2;
+    const releaseAccounts = {
+      buyer: buyer.publicKey,
+      seller: seller.publicKey,
+      feeRecipient: feeRecipient.publicKey,
+    };
+
+    await program.methods
+      .initEscrow(new anchor.BN(0), new anchor.BN(0), milestones)
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+
+    // The agreed amount must match the milestone total
+    try {
+      await program.methods
+        .acceptEscrow(new anchor.BN(total - 1), Array(32).fill(0), [])
+        .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+        .signers([seller])
+        .rpc();
+      assert.fail("Accepting a different amount should fail");
+    } catch (err) {
+      assert.include(err.toString(), "MilestoneTotalMismatch");
+    }
+
+    await program.methods
+      .acceptEscrow(new anchor.BN(total), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(total))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    const first = milestones[0].amount.toNumber();
+    const sellerBalanceBefore = await provider.connection.getBalance(seller.publicKey);
+    await program.methods.releaseMilestone(0).accounts(releaseAccounts).rpc();
+
+    const fee = (first * feeBps) / 10_000;
+    assert.equal(
+      await provider.connection.getBalance(seller.publicKey),
+      sellerBalanceBefore + first - fee,
+      "Seller should receive the first milestone minus the fee"
+    );
+    let escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.ok("released" in escrow.milestones[0].status, "First milestone should be released");
+    assert.ok("pending" in escrow.milestones[1].status, "Second milestone should still be pending");
+    assert.ok("buyerDeposit" in escrow.state, "Escrow should stay open until every milestone settles");
+    assert.equal(escrow.amount.toNumber(), total - first, "Escrow amount mismatch after first milestone");
+
+    for (const settle of [
+      () => program.methods.releaseMilestone(0).accounts(releaseAccounts).rpc(),
+      () => program.methods.refundMilestone(0).accounts({ buyer: buyer.publicKey }).rpc(),
+    ]) {
+      try {
+        await settle();
+        assert.fail("A settled milestone should not settle again");
+      } catch (err) {
+        assert.include(err.toString(), "MilestoneAlreadySettled");
+      }
+    }
+
+    try {
+      await program.methods.releaseMilestone(2).accounts(releaseAccounts).rpc();
+      assert.fail("An out of range milestone should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidMilestone");
+    }
+
+    const escrowBalanceBefore = await provider.connection.getBalance(escrowPda);
+    await program.methods.refundMilestone(1).accounts({ buyer: buyer.publicKey }).rpc();
+
+    escrow = await program.account.escrowAccount.fetch(escrowPda);
+    assert.ok("refunded" in escrow.milestones[1].status, "Second milestone should be refunded");
+    assert.ok("completed" in escrow.state, "Escrow should complete once the last milestone settles");
+    assert.equal(escrow.amount.toNumber(), 0, "Escrow should be empty");
+    assert.equal(
+      await provider.connection.getBalance(escrowPda),
+      escrowBalanceBefore - milestones[1].amount.toNumber(),
+      "Second milestone should go back to the buyer"
+    );
+
+    await program.methods.closeEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+  });
+
+  it("either party can back out before the escrow is funded", async () => {
+    const agreed = LAMPORTS_PER_SOL / 4;
+    const initEscrow = () =>
+      program.methods
+        .initEscrow(new anchor.BN(0), new anchor.BN(0), [])
+        .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+        .rpc();
+    const acceptEscrow = () =>
+      program.methods
+        .acceptEscrow(new anchor.BN(agreed), Array(32).fill(0), [])
+        .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+        .signers([seller])
+        .rpc();
+
+    // Seller declines a pending escrow
+    await initEscrow();
+    await program.methods
+      .declineEscrow()
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Declined escrow should be closed");
+
+    // Buyer cancels while the seller has not answered
+    await initEscrow();
+    await program.methods.cancelEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Cancelled escrow should be closed");
+
+    // Buyer cancels after acceptance, as long as nothing was deposited
+    await initEscrow();
+    await acceptEscrow();
+    await program.methods.cancelEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Cancelled escrow should be closed");
+
+    // Once funded, the deposit has to be refunded instead
+    await initEscrow();
+    await acceptEscrow();
+    await program.methods
+      .deposit(new anchor.BN(agreed))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+    try {
+      await program.methods.cancelEscrow().accounts({ buyer: buyer.publicKey }).rpc();
+      assert.fail("A funded escrow should not be cancelled");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidState");
+    }
+    try {
+      await program.methods
+        .declineEscrow()
+        .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+        .signers([seller])
+        .rpc();
+      assert.fail("A funded escrow should not be declined");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidState");
+    }
+    await program.methods.refund(true).accounts({ buyer: buyer.publicKey }).rpc();
+  });
+
+  it("oracle escrows ignore expired deadlines", async () => {
+    const oracle = anchor.web3.Keypair.generate();
+    const condition = Buffer.alloc(32, 3);
+    const depositAmount = LAMPORTS_PER_SOL / 4;
+
+    // Both deadlines are long past
+    await program.methods
+      .initEscrow(new anchor.BN(1), new anchor.BN(1), [])
+      .accounts({ buyer: buyer.publicKey, seller: seller.publicKey })
+      .rpc();
+    await program.methods
+      .setOracleCondition(oracle.publicKey, [...condition])
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+    await program.methods
+      .acceptEscrow(new anchor.BN(depositAmount), Array(32).fill(0), [])
+      .accounts({ seller: seller.publicKey, buyer: buyer.publicKey })
+      .signers([seller])
+      .rpc();
+    await program.methods
+      .deposit(new anchor.BN(depositAmount))
+      .accounts({ buyer: buyer.publicKey })
+      .rpc();
+
+    try {
+      await program.methods
+        .claimAfterDeadline()
+        .accounts({
+          caller: buyer.publicKey,
+          buyer: buyer.publicKey,
+          seller: seller.publicKey,
+          feeRecipient: feeRecipient.publicKey,
+        })
+        .rpc();
+      assert.fail("Oracle escrows should not release on a deadline");
+    } catch (err) {
+      assert.include(err.toString(), "OracleRequired");
+    }
+    try {
+      await program.methods
+        .reclaimAfterDeadline()
+        .accounts({ caller: buyer.publicKey, buyer: buyer.publicKey })
+        .rpc();
+      assert.fail("Oracle escrows should not refund on a deadline");
+    } catch (err) {
+      assert.include(err.toString(), "OracleRequired");
+    }
+
+    // The oracle's refund attestation (0 = refund) still unwinds it
+    const { createdAt } = await program.account.escrowAccount.fetch(escrowPda);
+    const attestation = Ed25519Program.createInstructionWithPrivateKey({
+      privateKey: oracle.secretKey,
+      message: Buffer.concat([
+        escrowPda.toBuffer(),
+        createdAt.toArrayLike(Buffer, "le", 8),
+        condition,
+        Buffer.from([0]),
+      ]),
+    });
+    await program.methods
+      .refund(true)
+      .accounts({ buyer: buyer.publicKey })
+      .preInstructions([attestation])
+      .rpc();
+    assert.isNull(await provider.connection.getAccountInfo(escrowPda), "Escrow should be closed on refund");
   });
 });
@@ -0,0 +1,980 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { VaultContract } from "../target/types/vault_contract";
+import {
+  createMint,
+  getOrCreateAssociatedTokenAccount,
+  getAccount,
+  mintTo,
+  TOKEN_PROGRAM_ID,
+} from "@solana/spl-token";
+import { assert } from "chai";
+import { createHash } from "crypto";
+import { BankrunProvider } from "anchor-bankrun";
+import { Clock, startAnchor } from "solana-bankrun";
+import * as bankrunToken from "spl-token-bankrun";
+import { MockStrategy } from "../target/types/mock_strategy";
+
+describe("vault_contract", () => {
+  const provider = anchor.AnchorProvider.local();
+  anchor.setProvider(provider);
+  const program = anchor.workspace.vaultContract as Program<VaultContract>;
+  const owner = provider.wallet;
+
+  const MINIMUM_LOCKED_SHARES = 1_000;
+  const PRICE_SCALE = new anchor.BN(1_000_000_000);
+  const U64_MAX = "18446744073709551615";
+
+  let assetMint: anchor.web3.PublicKey;
+  let vault: anchor.web3.PublicKey;
+  let assetVault: anchor.web3.PublicKey;
+  let shareMint: anchor.web3.PublicKey;
+  let position: anchor.web3.PublicKey;
+  let ownerAssets: anchor.web3.PublicKey;
+  let ownerShares: anchor.web3.PublicKey;
+
+  // Accounts shared by deposit and the withdraw instructions
+  const depositAccounts = () => ({
+    depositor: owner.publicKey,
+    vault,
+    assetVault,
+    depositorAssets: ownerAssets,
+    shareMint,
+    depositorShares: ownerShares,
+    position,
+    tokenProgram: TOKEN_PROGRAM_ID,
+  });
+
+  const shareBalance = async () =>
+    Number((await getAccount(provider.connection, ownerShares)).amount);
+
+  const assetBalance = async (account: anchor.web3.PublicKey) =>
+    Number((await getAccount(provider.connection, account)).amount);
+
+  // Views are simulated, so their errors are reported in the logs
+  const errorText = (err) =>
+    [err.toString(), ...(err.simulationResponse?.logs ?? err.logs ?? [])].join("\n");
+
+  // Send assets straight to the vault without minting shares
+  const donate = (amount: number) =>
+    mintTo(provider.connection, owner.payer, assetMint, assetVault, owner.payer, amount);
+
+  before(async () => {
+    assetMint = await createMint(
+      provider.connection,
+      owner.payer,
+      owner.publicKey,
+      null,
+      6 // decimals
+    );
+
+    [vault] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), assetMint.toBuffer(), owner.publicKey.toBuffer()],
+      program.programId
+    );
+    [assetVault] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_assets"), vault.toBuffer()],
+      program.programId
+    );
+    [shareMint] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("share_mint"), vault.toBuffer()],
+      program.programId
+    );
+    [position] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("position"), vault.toBuffer(), owner.publicKey.toBuffer()],
+      program.programId
+    );
+
+    ownerAssets = (
+      await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        owner.payer,
+        assetMint,
+        owner.publicKey
+      )
+    ).address;
+    await mintTo(provider.connection, owner.payer, assetMint, ownerAssets, owner.payer, 100_000_000);
+  });
+
+  it("initialize vault and open a position", async () => {
+    await program.methods
+      .initialize()
+      .accounts({
+        owner: owner.publicKey,
+        assetMint,
+        vault,
+        assetVault,
+        shareMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    await program.methods
+      .openPosition()
+      .accounts({
+        depositor: owner.publicKey,
+        vault,
+        position,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    ownerShares = (
+      await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        owner.payer,
+        shareMint,
+        owner.publicKey
+      )
+    ).address;
+
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.ok(vaultAccount.assetMint.equals(assetMint), "Asset mint mismatch");
+    assert.ok(vaultAccount.shareMint.equals(shareMint), "Share mint mismatch");
+    assert.equal(vaultAccount.totalShares.toNumber(), 0, "Vault should start without shares");
+  });
+
+  it("first deposit locks the minimum shares", async () => {
+    const amount = 1_000_000;
+
+    await program.methods
+      .deposit(new anchor.BN(amount), [])
+      .accounts(depositAccounts())
+      .rpc();
+
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.totalShares.toNumber(), amount, "Total shares mismatch");
+    assert.equal(await shareBalance(), amount - MINIMUM_LOCKED_SHARES, "Locked shares should not be minted");
+    assert.equal(await assetBalance(assetVault), amount, "Vault asset balance mismatch");
+  });
+
+  it("donations raise the share price instead of diluting depositors", async () => {
+    // Doubles the assets behind the existing 1_000_000 shares
+    await donate(1_000_000);
+
+    const sharesBefore = await shareBalance();
+    await program.methods
+      .deposit(new anchor.BN(1_000_000), [])
+      .accounts(depositAccounts())
+      .rpc();
+
+    // 1_000_000 * 1_000_000 shares / 2_000_000 assets
+    assert.equal(await shareBalance(), sharesBefore + 500_000, "Deposit should be priced at 2 assets per share");
+
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.totalShares.toNumber(), 1_500_000, "Total shares mismatch");
+    assert.equal(await assetBalance(assetVault), 3_000_000, "Vault asset balance mismatch");
+  });
+
+  it("withdraws partially by shares and by assets", async () => {
+    // 3_000_000 assets back 1_500_000 shares
+    let assetsBefore = await assetBalance(ownerAssets);
+    let sharesBefore = await shareBalance();
+    await program.methods
+      .withdrawShares(new anchor.BN(100_000))
+      .accounts(depositAccounts())
+      .rpc();
+    assert.equal(await shareBalance(), sharesBefore - 100_000, "Shares burned mismatch");
+    assert.equal(await assetBalance(ownerAssets), assetsBefore + 200_000, "Assets paid mismatch");
+
+    assetsBefore = await assetBalance(ownerAssets);
+    sharesBefore = await shareBalance();
+    await program.methods
+      .withdrawAssets(new anchor.BN(200_000))
+      .accounts(depositAccounts())
+      .rpc();
+    assert.equal(await shareBalance(), sharesBefore - 100_000, "Shares burned mismatch");
+    assert.equal(await assetBalance(ownerAssets), assetsBefore + 200_000, "Assets paid mismatch");
+
+    try {
+      await program.methods
+        .withdrawShares(new anchor.BN(sharesBefore))
+        .accounts(depositAccounts())
+        .rpc();
+      assert.fail("Withdrawing more shares than held should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientShares");
+    }
+  });
+
+  it("full withdrawal leaves the share account open", async () => {
+    await program.methods
+      .withdraw()
+      .accounts(depositAccounts())
+      .rpc();
+
+    assert.equal(await shareBalance(), 0, "All shares should be redeemed");
+
+    // Only the locked shares remain, still worth 2 assets each
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.totalShares.toNumber(), MINIMUM_LOCKED_SHARES, "Locked shares should remain");
+    assert.equal(await assetBalance(assetVault), 2 * MINIMUM_LOCKED_SHARES, "Vault asset balance mismatch");
+  });
+
+  it("high-water mark follows gains even with no fee configured", async () => {
+    // The donation doubled the share price while no performance fee was set
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(
+      vaultAccount.highWaterMark.toString(),
+      PRICE_SCALE.muln(2).toString(),
+      "High-water mark should track the 2x share price"
+    );
+  });
+
+  it("accrues performance fees only on gains above the high-water mark", async () => {
+    const performanceFeeBps = 2_000; // 20%
+
+    await program.methods
+      .setFees(0, performanceFeeBps)
+      .accounts({ owner: owner.publicKey, vault, assetVault })
+      .rpc();
+
+    // Priced at the high-water mark, so no fee is due yet
+    await program.methods
+      .deposit(new anchor.BN(1_000_000), [])
+      .accounts(depositAccounts())
+      .rpc();
+    let vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.feeShares.toNumber(), 0, "No fee should accrue without gains");
+
+    // Lift the share price from 2 to 3 assets per share
+    const supply = vaultAccount.totalShares;
+    const assets = new anchor.BN(await assetBalance(assetVault)).add(supply);
+    await donate(supply.toNumber());
+
+    const price = assets.mul(PRICE_SCALE).div(supply);
+    const feeAssets = price
+      .sub(vaultAccount.highWaterMark)
+      .mul(supply)
+      .muln(performanceFeeBps)
+      .div(PRICE_SCALE.muln(10_000));
+    const expectedFeeShares = feeAssets.mul(supply).div(assets.sub(feeAssets));
+
+    const sharesBefore = await shareBalance();
+    await program.methods
+      .claimFees()
+      .accounts({
+        owner: owner.publicKey,
+        vault,
+        assetVault,
+        shareMint,
+        ownerShares,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    assert.equal(
+      await shareBalance(),
+      sharesBefore + expectedFeeShares.toNumber(),
+      "Owner should be minted the performance fee shares"
+    );
+
+    vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.feeShares.toNumber(), 0, "Claimed fee shares should be cleared");
+    assert.equal(
+      vaultAccount.highWaterMark.toString(),
+      assets.mul(PRICE_SCALE).div(supply.add(expectedFeeShares)).toString(),
+      "High-water mark should move to the post-fee share price"
+    );
+  });
+
+  it("owner cannot withdraw depositor funds as fees", async () => {
+    // Fee shares were all claimed as tokens above, so none are left to redeem
+    try {
+      await program.methods
+        .ownerWithdraw(new anchor.BN(1))
+        .accounts({
+          owner: owner.publicKey,
+          vault,
+          assetVault,
+          ownerAssets,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      assert.fail("Owner withdrawal should be limited to accrued fees");
+    } catch (err) {
+      assert.include(err.toString(), "ExceedsAccruedFees");
+    }
+  });
+
+  it("announced transfers wait for the timelock", async () => {
+    await program.methods
+      .announceTransfer(new anchor.BN(1_000))
+      .accounts({ owner: owner.publicKey, vault, destination: ownerAssets })
+      .rpc();
+
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.pendingTransferAmount.toNumber(), 1_000, "Pending amount mismatch");
+    assert.ok(vaultAccount.pendingTransferDestination.equals(ownerAssets), "Pending destination mismatch");
+
+    try {
+      await program.methods
+        .executeTransfer()
+        .accounts({
+          owner: owner.publicKey,
+          vault,
+          assetVault,
+          destination: ownerAssets,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      assert.fail("Transfer should not execute before the timelock");
+    } catch (err) {
+      assert.include(err.toString(), "TimelockNotElapsed");
+    }
+
+    await program.methods
+      .cancelTransfer()
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+    const cancelled = await program.account.vault.fetch(vault);
+    assert.equal(cancelled.pendingTransferEta.toNumber(), 0, "Transfer should be cancelled");
+  });
+
+  it("rejects strategies that could move vault tokens directly", async () => {
+    for (const programId of [TOKEN_PROGRAM_ID, program.programId]) {
+      try {
+        await program.methods
+          .addStrategy(programId)
+          .accounts({ owner: owner.publicKey, vault })
+          .rpc();
+        assert.fail("Strategy should be rejected");
+      } catch (err) {
+        assert.include(err.toString(), "StrategyNotAllowed");
+      }
+    }
+  });
+
+  it("strategies cannot be used before their timelock", async () => {
+    const strategyProgram = anchor.web3.ComputeBudgetProgram.programId;
+    const strategyAccounts = {
+      owner: owner.publicKey,
+      vault,
+      assetVault,
+      shareMint,
+      strategyProgram,
+    };
+
+    await program.methods
+      .addStrategy(strategyProgram)
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.strategies.length, 1, "Strategy should be registered");
+
+    try {
+      await program.methods
+        .allocate(0, new anchor.BN(1_000), Buffer.from([]))
+        .accounts(strategyAccounts)
+        .rpc();
+      assert.fail("Allocation should wait for the timelock");
+    } catch (err) {
+      assert.include(err.toString(), "TimelockNotElapsed");
+    }
+
+    try {
+      await program.methods
+        .harvest(0, Buffer.from([]))
+        .accounts(strategyAccounts)
+        .rpc();
+      assert.fail("Harvest should wait for the timelock");
+    } catch (err) {
+      assert.include(err.toString(), "TimelockNotElapsed");
+    }
+
+    try {
+      await program.methods
+        .deallocate(0, new anchor.BN(0), Buffer.from([]))
+        .accounts(strategyAccounts)
+        .rpc();
+      assert.fail("Deallocating nothing should fail");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidAmount");
+    }
+
+    await program.methods
+      .removeStrategy(0)
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+    const removed = await program.account.vault.fetch(vault);
+    assert.equal(removed.strategies.length, 0, "Strategy should be removed");
+  });
+
+  it("queues a withdrawal for the current epoch", async () => {
+    const shares = 100_000;
+    const epoch = new anchor.BN(0);
+    const [request] = anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("withdrawal"),
+        vault.toBuffer(),
+        owner.publicKey.toBuffer(),
+        epoch.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+    const [epochRecord] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("epoch"), vault.toBuffer(), epoch.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+
+    const totalSharesBefore = (await program.account.vault.fetch(vault)).totalShares.toNumber();
+    const sharesBefore = await shareBalance();
+    await program.methods
+      .requestWithdrawal(new anchor.BN(shares))
+      .accounts({
+        depositor: owner.publicKey,
+        vault,
+        request,
+        shareMint,
+        depositorShares: ownerShares,
+        position,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    // Queued shares are burned now but stay in the supply until the epoch closes
+    assert.equal(await shareBalance(), sharesBefore - shares, "Queued shares should be burned");
+    const vaultAccount = await program.account.vault.fetch(vault);
+    assert.equal(vaultAccount.epochQueuedShares.toNumber(), shares, "Queued shares mismatch");
+    assert.equal(vaultAccount.totalShares.toNumber(), totalSharesBefore, "Supply should be unchanged");
+
+    const requestAccount = await program.account.withdrawalRequest.fetch(request);
+    assert.ok(requestAccount.owner.equals(owner.publicKey), "Request owner mismatch");
+    assert.equal(requestAccount.epoch.toNumber(), 0, "Request epoch mismatch");
+    assert.equal(requestAccount.shares.toNumber(), shares, "Request shares mismatch");
+    const positionAccount = await program.account.userPosition.fetch(position);
+    assert.equal(positionAccount.openRequests, 1, "Position should track the open request");
+
+    // Claims settle only after the epoch has run its full duration
+    try {
+      await program.methods
+        .closeEpoch()
+        .accounts({
+          payer: owner.publicKey,
+          vault,
+          assetVault,
+          epochRecord,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+      assert.fail("Epoch should not close early");
+    } catch (err) {
+      assert.include(err.toString(), "EpochNotOver");
+    }
+  });
+
+  it("enforces the total and per-user deposit caps", async () => {
+    const noAllowlist = Array(32).fill(0);
+    const totalAssets = await assetBalance(assetVault);
+
+    await program.methods
+      .setLimits(new anchor.BN(totalAssets + 100), new anchor.BN(0), noAllowlist)
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+    try {
+      await program.methods
+        .deposit(new anchor.BN(101), [])
+        .accounts(depositAccounts())
+        .rpc();
+      assert.fail("Deposit above the vault cap should fail");
+    } catch (err) {
+      assert.include(err.toString(), "DepositCapExceeded");
+    }
+
+    // The per-user cap counts net deposits recorded on the position
+    const deposited = (await program.account.userPosition.fetch(position)).deposited.toNumber();
+    await program.methods
+      .setLimits(new anchor.BN(U64_MAX), new anchor.BN(deposited + 100), noAllowlist)
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+    try {
+      await program.methods
+        .deposit(new anchor.BN(101), [])
+        .accounts(depositAccounts())
+        .rpc();
+      assert.fail("Deposit above the user cap should fail");
+    } catch (err) {
+      assert.include(err.toString(), "UserCapExceeded");
+    }
+
+    await program.methods
+      .deposit(new anchor.BN(100), [])
+      .accounts(depositAccounts())
+      .rpc();
+    const positionAccount = await program.account.userPosition.fetch(position);
+    assert.equal(positionAccount.deposited.toNumber(), deposited + 100, "Position should track the deposit");
+  });
+
+  it("only allowlisted depositors can deposit", async () => {
+    const leaf = (key: anchor.web3.PublicKey) => [...createHash("sha256").update(key.toBuffer()).digest()];
+
+    await program.methods
+      .setLimits(new anchor.BN(U64_MAX), new anchor.BN(0), leaf(anchor.web3.Keypair.generate().publicKey))
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+    try {
+      await program.methods
+        .deposit(new anchor.BN(100), [])
+        .accounts(depositAccounts())
+        .rpc();
+      assert.fail("Depositor outside the allowlist should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "NotAllowlisted");
+    }
+
+    // A single-leaf tree has the leaf as its root and an empty proof
+    await program.methods
+      .setLimits(new anchor.BN(U64_MAX), new anchor.BN(0), leaf(owner.publicKey))
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+    const sharesBefore = await shareBalance();
+    await program.methods
+      .deposit(new anchor.BN(100), [])
+      .accounts(depositAccounts())
+      .rpc();
+    assert.isAbove(await shareBalance(), sharesBefore, "Allowlisted depositor should receive shares");
+
+    await program.methods
+      .setLimits(new anchor.BN(U64_MAX), new anchor.BN(0), Array(32).fill(0))
+      .accounts({ owner: owner.publicKey, vault })
+      .rpc();
+  });
+
+  it("pausing stops deposits but not withdrawals", async () => {
+    await program.methods
+      .setPaused(true)
+      .accounts({ authority: owner.publicKey, vault })
+      .rpc();
+
+    try {
+      await program.methods
+        .deposit(new anchor.BN(1_000), [])
+        .accounts(depositAccounts())
+        .rpc();
+      assert.fail("Deposits should be paused");
+    } catch (err) {
+      assert.include(err.toString(), "VaultPaused");
+    }
+
+    const sharesBefore = await shareBalance();
+    await program.methods
+      .withdrawShares(new anchor.BN(1_000))
+      .accounts(depositAccounts())
+      .rpc();
+    assert.equal(await shareBalance(), sharesBefore - 1_000, "Withdrawals should stay open");
+
+    await program.methods
+      .setPaused(false)
+      .accounts({ authority: owner.publicKey, vault })
+      .rpc();
+  });
+
+  it("views match what the instructions do", async () => {
+    const viewAccounts = { vault, assetVault };
+
+    const totalAssets = await program.methods.totalAssets().accounts(viewAccounts).view();
+    assert.equal(totalAssets.toNumber(), await assetBalance(assetVault), "Total assets mismatch");
+
+    const amount = new anchor.BN(10_000);
+    const previewShares = await program.methods
+      .previewDeposit(amount, [])
+      .accounts({ vault, assetVault, position })
+      .view();
+    const convertedShares = await program.methods.convertToShares(amount).accounts(viewAccounts).view();
+    assert.equal(previewShares.toNumber(), convertedShares.toNumber(), "Preview should match the share price");
+
+    let sharesBefore = await shareBalance();
+    await program.methods
+      .deposit(amount, [])
+      .accounts(depositAccounts())
+      .rpc();
+    assert.equal(await shareBalance(), sharesBefore + previewShares.toNumber(), "Deposit should mint the previewed shares");
+
+    const previewBurn = await program.methods.previewWithdraw(amount).accounts(viewAccounts).view();
+    sharesBefore = await shareBalance();
+    await program.methods
+      .withdrawAssets(amount)
+      .accounts(depositAccounts())
+      .rpc();
+    assert.equal(await shareBalance(), sharesBefore - previewBurn.toNumber(), "Withdrawal should burn the previewed shares");
+  });
+
+  it("preview_deposit fails wherever deposit would", async () => {
+    await program.methods
+      .setPaused(true)
+      .accounts({ authority: owner.publicKey, vault })
+      .rpc();
+    try {
+      await program.methods
+        .previewDeposit(new anchor.BN(10_000), [])
+        .accounts({ vault, assetVault, position })
+        .view();
+      assert.fail("Preview should fail while paused");
+    } catch (err) {
+      assert.include(errorText(err), "VaultPaused");
+    }
+    await program.methods
+      .setPaused(false)
+      .accounts({ authority: owner.publicKey, vault })
+      .rpc();
+
+    // A fresh vault must take a first deposit above the locked minimum
+    const otherMint = await createMint(provider.connection, owner.payer, owner.publicKey, null, 6);
+    const [otherVault] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), otherMint.toBuffer(), owner.publicKey.toBuffer()],
+      program.programId
+    );
+    const [otherAssetVault] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_assets"), otherVault.toBuffer()],
+      program.programId
+    );
+    const [otherShareMint] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("share_mint"), otherVault.toBuffer()],
+      program.programId
+    );
+    const [otherPosition] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("position"), otherVault.toBuffer(), owner.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .initialize()
+      .accounts({
+        owner: owner.publicKey,
+        assetMint: otherMint,
+        vault: otherVault,
+        assetVault: otherAssetVault,
+        shareMint: otherShareMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+    await program.methods
+      .openPosition()
+      .accounts({
+        depositor: owner.publicKey,
+        vault: otherVault,
+        position: otherPosition,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    try {
+      await program.methods
+        .previewDeposit(new anchor.BN(MINIMUM_LOCKED_SHARES), [])
+        .accounts({ vault: otherVault, assetVault: otherAssetVault, position: otherPosition })
+        .view();
+      assert.fail("Preview of a too-small first deposit should fail");
+    } catch (err) {
+      assert.include(errorText(err), "DepositTooSmall");
+    }
+
+    // Positions are bound to their vault
+    try {
+      await program.methods
+        .previewDeposit(new anchor.BN(10_000), [])
+        .accounts({ vault: otherVault, assetVault: otherAssetVault, position })
+        .view();
+      assert.fail("Preview should reject another vault's position");
+    } catch (err) {
+      assert.include(errorText(err), "ConstraintHasOne");
+    }
+  });
+
+  it("closes a position only once it holds nothing", async () => {
+    const openDepositor = async () => {
+      const depositor = anchor.web3.Keypair.generate();
+      await provider.connection.confirmTransaction(
+        await provider.connection.requestAirdrop(depositor.publicKey, anchor.web3.LAMPORTS_PER_SOL)
+      );
+      const [depositorPosition] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("position"), vault.toBuffer(), depositor.publicKey.toBuffer()],
+        program.programId
+      );
+      const depositorAssets = (
+        await getOrCreateAssociatedTokenAccount(provider.connection, owner.payer, assetMint, depositor.publicKey)
+      ).address;
+      const depositorShares = (
+        await getOrCreateAssociatedTokenAccount(provider.connection, owner.payer, shareMint, depositor.publicKey)
+      ).address;
+      await mintTo(provider.connection, owner.payer, assetMint, depositorAssets, owner.payer, 100_000);
+
+      await program.methods
+        .openPosition()
+        .accounts({
+          depositor: depositor.publicKey,
+          vault,
+          position: depositorPosition,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([depositor])
+        .rpc();
+      const accounts = {
+        depositor: depositor.publicKey,
+        vault,
+        assetVault,
+        depositorAssets,
+        shareMint,
+        depositorShares,
+        position: depositorPosition,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      };
+      await program.methods
+        .deposit(new anchor.BN(50_000), [])
+        .accounts(accounts)
+        .signers([depositor])
+        .rpc();
+      return { depositor, accounts };
+    };
+    const closePosition = ({ depositor, accounts }) =>
+      program.methods
+        .closePosition()
+        .accounts({
+          depositor: depositor.publicKey,
+          vault,
+          shareMint,
+          depositorShares: accounts.depositorShares,
+          position: accounts.position,
+        })
+        .signers([depositor])
+        .rpc();
+
+    const exited = await openDepositor();
+    try {
+      await closePosition(exited);
+      assert.fail("A position with shares should stay open");
+    } catch (err) {
+      assert.include(err.toString(), "PositionNotEmpty");
+    }
+
+    await program.methods
+      .withdraw()
+      .accounts(exited.accounts)
+      .signers([exited.depositor])
+      .rpc();
+    const rent = await provider.connection.getBalance(exited.accounts.position);
+    const balanceBefore = await provider.connection.getBalance(exited.depositor.publicKey);
+    await closePosition(exited);
+
+    assert.isNull(await provider.connection.getAccountInfo(exited.accounts.position), "Position should be closed");
+    assert.equal(
+      await provider.connection.getBalance(exited.depositor.publicKey),
+      balanceBefore + rent,
+      "Depositor should get the position rent back"
+    );
+
+    // Queuing every share empties the share account but leaves a claim open
+    const queued = await openDepositor();
+    const { currentEpoch } = await program.account.vault.fetch(vault);
+    const [request] = anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("withdrawal"),
+        vault.toBuffer(),
+        queued.depositor.publicKey.toBuffer(),
+        currentEpoch.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+    const shares = Number((await getAccount(provider.connection, queued.accounts.depositorShares)).amount);
+    await program.methods
+      .requestWithdrawal(new anchor.BN(shares))
+      .accounts({
+        depositor: queued.depositor.publicKey,
+        vault,
+        request,
+        shareMint,
+        depositorShares: queued.accounts.depositorShares,
+        position: queued.accounts.position,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([queued.depositor])
+      .rpc();
+
+    try {
+      await closePosition(queued);
+      assert.fail("A position with a queued withdrawal should stay open");
+    } catch (err) {
+      assert.include(err.toString(), "PositionNotEmpty");
+    }
+  });
+
+  it("accounts for allocations to and returns from a strategy", async () => {
+    // Bankrun lets the clock skip past the strategy timelock
+    const context = await startAnchor("", [], []);
+    const client = context.banksClient;
+    const payer = context.payer;
+    const bankrun = new BankrunProvider(context);
+    const vaultProgram = new Program<VaultContract>(program.idl, bankrun);
+    const strategy = new Program<MockStrategy>(anchor.workspace.mockStrategy.idl, bankrun);
+    const TRANSFER_TIMELOCK = 7 * 24 * 60 * 60;
+    const pda = (programId: anchor.web3.PublicKey, ...seeds: Buffer[]) =>
+      anchor.web3.PublicKey.findProgramAddressSync(seeds, programId)[0];
+    const balance = async (account: anchor.web3.PublicKey) =>
+      Number((await bankrunToken.getAccount(client, account)).amount);
+
+    const mint = await bankrunToken.createMint(client, payer, payer.publicKey, null, 6);
+    const vault = pda(vaultProgram.programId, Buffer.from("vault"), mint.toBuffer(), payer.publicKey.toBuffer());
+    const assetVault = pda(vaultProgram.programId, Buffer.from("vault_assets"), vault.toBuffer());
+    const shareMint = pda(vaultProgram.programId, Buffer.from("share_mint"), vault.toBuffer());
+    const position = pda(vaultProgram.programId, Buffer.from("position"), vault.toBuffer(), payer.publicKey.toBuffer());
+    const authority = pda(strategy.programId, Buffer.from("authority"));
+    const strategyAssets = pda(strategy.programId, Buffer.from("strategy_assets"), vault.toBuffer());
+
+    await vaultProgram.methods
+      .initialize()
+      .accounts({
+        owner: payer.publicKey,
+        assetMint: mint,
+        vault,
+        assetVault,
+        shareMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+    await vaultProgram.methods
+      .openPosition()
+      .accounts({
+        depositor: payer.publicKey,
+        vault,
+        position,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const payerAssets = await bankrunToken.createAccount(client, payer, mint, payer.publicKey);
+    const payerShares = await bankrunToken.createAccount(client, payer, shareMint, payer.publicKey);
+    await bankrunToken.mintTo(client, payer, mint, payerAssets, payer, 10_000_000);
+    await vaultProgram.methods
+      .deposit(new anchor.BN(1_000_000), [])
+      .accounts({
+        depositor: payer.publicKey,
+        vault,
+        assetVault,
+        depositorAssets: payerAssets,
+        shareMint,
+        depositorShares: payerShares,
+        position,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    await vaultProgram.methods
+      .addStrategy(strategy.programId)
+      .accounts({ owner: payer.publicKey, vault })
+      .rpc();
+    await strategy.methods
+      .initialize()
+      .accounts({
+        payer: payer.publicKey,
+        vault,
+        assetMint: mint,
+        authority,
+        strategyAssets,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      })
+      .rpc();
+
+    const clock = await client.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        clock.unixTimestamp + BigInt(TRANSFER_TIMELOCK)
+      )
+    );
+
+    // Accounts for the strategy call, in the order the mock strategy expects
+    const strategyAccounts = {
+      owner: payer.publicKey,
+      vault,
+      assetVault,
+      shareMint,
+      strategyProgram: strategy.programId,
+    };
+    const remainingAccounts = [
+      { pubkey: vault, isSigner: false, isWritable: false },
+      { pubkey: assetVault, isSigner: false, isWritable: true },
+      { pubkey: strategyAssets, isSigner: false, isWritable: true },
+      { pubkey: authority, isSigner: false, isWritable: false },
+      { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+    ];
+    const callData = (name: string, amount: number) =>
+      strategy.coder.instruction.encode(name, { amount: new anchor.BN(amount) });
+
+    await vaultProgram.methods
+      .allocate(0, new anchor.BN(400_000), callData("deposit", 400_000))
+      .accounts(strategyAccounts)
+      .remainingAccounts(remainingAccounts)
+      .rpc();
+
+    let vaultAccount = await vaultProgram.account.vault.fetch(vault);
+    assert.equal(vaultAccount.strategies[0].allocated.toNumber(), 400_000, "Strategy allocation mismatch");
+    assert.equal(vaultAccount.totalAllocated.toNumber(), 400_000, "Total allocation mismatch");
+    assert.equal(await balance(assetVault), 600_000, "Allocated assets should leave the vault");
+    assert.equal(await balance(strategyAssets), 400_000, "Strategy should hold the allocation");
+
+    try {
+      await vaultProgram.methods
+        .allocate(0, new anchor.BN(100_000), callData("deposit", 200_000))
+        .accounts(strategyAccounts)
+        .remainingAccounts(remainingAccounts)
+        .rpc();
+      assert.fail("Strategy should not take more than allocated");
+    } catch (err) {
+      assert.include(errorText(err), "AllocationExceeded");
+    }
+
+    // Moving nothing is not enough; the vault's token authorities must be untouched
+    try {
+      await vaultProgram.methods
+        .allocate(0, new anchor.BN(100_000), callData("approve", 100_000))
+        .accounts(strategyAccounts)
+        .remainingAccounts(remainingAccounts)
+        .rpc();
+      assert.fail("Strategy should not leave a delegate on the vault");
+    } catch (err) {
+      assert.include(errorText(err), "VaultAuthorityChanged");
+    }
+
+    // Yield accrues in the strategy and comes back as a gain on deallocation
+    await bankrunToken.mintTo(client, payer, mint, strategyAssets, payer, 50_000);
+    await vaultProgram.methods
+      .deallocate(0, new anchor.BN(400_000), callData("withdraw", 450_000))
+      .accounts(strategyAccounts)
+      .remainingAccounts(remainingAccounts)
+      .rpc();
+
+    vaultAccount = await vaultProgram.account.vault.fetch(vault);
+    assert.equal(vaultAccount.strategies[0].allocated.toNumber(), 0, "Strategy allocation should be cleared");
+    assert.equal(vaultAccount.totalAllocated.toNumber(), 0, "Total allocation should be cleared");
+    assert.equal(await balance(assetVault), 1_050_000, "Vault should hold the returned assets and gain");
+    assert.equal(await balance(strategyAssets), 0, "Strategy should be empty");
+
+    try {
+      await vaultProgram.methods
+        .deallocate(0, new anchor.BN(1), callData("withdraw", 0))
+        .accounts(strategyAccounts)
+        .remainingAccounts(remainingAccounts)
+        .rpc();
+      assert.fail("Cannot deallocate more than the book value");
+    } catch (err) {
+      assert.include(errorText(err), "InsufficientFunds");
+    }
+
+    await vaultProgram.methods
+      .removeStrategy(0)
+      .accounts({ owner: payer.publicKey, vault })
+      .rpc();
+  });
+});